@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE relay_targets ADD COLUMN platform TEXT;
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS relay_groups (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL UNIQUE,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS relay_group_targets (
+  group_id INTEGER NOT NULL REFERENCES relay_groups(id) ON DELETE CASCADE,
+  target_id INTEGER NOT NULL REFERENCES relay_targets(id) ON DELETE CASCADE,
+  PRIMARY KEY (group_id, target_id)
+);
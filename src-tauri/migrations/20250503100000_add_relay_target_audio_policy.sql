@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE relay_targets ADD COLUMN audio_policy TEXT NOT NULL DEFAULT 'passthrough';
+ALTER TABLE relay_targets ADD COLUMN audio_file TEXT;
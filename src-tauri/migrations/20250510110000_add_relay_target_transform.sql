@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE relay_targets ADD COLUMN crop_mode TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE relay_targets ADD COLUMN crop_aspect TEXT;
+ALTER TABLE relay_targets ADD COLUMN crop_x INTEGER;
+ALTER TABLE relay_targets ADD COLUMN crop_y INTEGER;
+ALTER TABLE relay_targets ADD COLUMN crop_width INTEGER;
+ALTER TABLE relay_targets ADD COLUMN crop_height INTEGER;
+ALTER TABLE relay_targets ADD COLUMN rotate INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE relay_targets ADD COLUMN output_width INTEGER;
+ALTER TABLE relay_targets ADD COLUMN output_height INTEGER;
+ALTER TABLE relay_targets ADD COLUMN pad BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS overlay_settings (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  watermark_enabled BOOLEAN NOT NULL DEFAULT 0,
+  watermark_path TEXT,
+  watermark_position TEXT NOT NULL DEFAULT 'top_right',
+  watermark_opacity REAL NOT NULL DEFAULT 1.0,
+  text_enabled BOOLEAN NOT NULL DEFAULT 0,
+  text_mode TEXT NOT NULL DEFAULT 'static',
+  text_content TEXT NOT NULL DEFAULT '',
+  text_position TEXT NOT NULL DEFAULT 'bottom_left',
+  text_size INTEGER NOT NULL DEFAULT 36,
+  text_color TEXT NOT NULL DEFAULT 'white',
+  font_file TEXT
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE encoder_settings ADD COLUMN loudness_target REAL;
+ALTER TABLE encoder_settings ADD COLUMN audio_gain REAL;
+ALTER TABLE encoder_settings ADD COLUMN audio_limiter BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE encoder_settings ADD COLUMN audio_channels INTEGER;
+ALTER TABLE encoder_settings ADD COLUMN audio_sample_rate INTEGER;
+ALTER TABLE encoder_settings ADD COLUMN audio_channel_map TEXT NOT NULL DEFAULT 'none';
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS ingest_settings (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  handshake_timeout_secs INTEGER NOT NULL DEFAULT 10,
+  publish_timeout_secs INTEGER NOT NULL DEFAULT 30,
+  idle_timeout_secs INTEGER NOT NULL DEFAULT 15
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE ingest_settings ADD COLUMN bind_mode TEXT NOT NULL DEFAULT 'all';
+ALTER TABLE ingest_settings ADD COLUMN bind_address TEXT;
+
+CREATE TABLE IF NOT EXISTS ingest_access_rules (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  cidr TEXT NOT NULL,
+  action TEXT NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
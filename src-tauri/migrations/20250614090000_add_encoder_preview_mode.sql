@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE encoder_settings ADD COLUMN preview_mode TEXT NOT NULL DEFAULT 'hls';
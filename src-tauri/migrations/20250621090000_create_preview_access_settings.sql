@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS preview_access_settings (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  lan_access BOOLEAN NOT NULL DEFAULT 0,
+  access_token TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE encoder_settings ADD COLUMN thumbnail_interval_secs INTEGER NOT NULL DEFAULT 10;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE encoder_settings ADD COLUMN dvr_window_mins INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE encoder_settings ADD COLUMN dvr_max_size_mb INTEGER NOT NULL DEFAULT 2048;
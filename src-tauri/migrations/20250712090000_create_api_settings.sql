@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS api_settings (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  enabled BOOLEAN NOT NULL DEFAULT 0,
+  api_key TEXT NOT NULL
+);
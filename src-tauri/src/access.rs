@@ -0,0 +1,169 @@
+use std::net::IpAddr;
+
+use crate::db::{AccessAction, AccessRule};
+
+/// An address block such as `192.168.1.0/24` or `fd00::/8`. A bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid address in '{}'", value))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in '{}'", value))?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // treat ::ffff:a.b.c.d the same as a.b.c.d so v4 rules apply on dual stack sockets
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parsed ingest access rules. Deny rules always win; when any allow rule exists,
+/// only matching addresses get in.
+#[derive(Debug, Default)]
+pub struct AccessList {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    pub fn from_rules(rules: &[AccessRule]) -> Self {
+        let mut list = Self::default();
+        for rule in rules {
+            let Ok(cidr) = Cidr::parse(&rule.cidr) else {
+                eprintln!("⚠️ Skipping invalid access rule '{}'", rule.cidr);
+                continue;
+            };
+            match rule.action {
+                AccessAction::Allow => list.allow.push(cidr),
+                AccessAction::Deny => list.deny.push(cidr),
+            }
+        }
+        list
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn rule(cidr: &str, action: AccessAction) -> AccessRule {
+        AccessRule {
+            id: 0,
+            cidr: cidr.into(),
+            action,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn matches_v4_blocks() {
+        let cidr = Cidr::parse("192.168.1.0/24").unwrap();
+        assert!(cidr.contains(ip("192.168.1.42")));
+        assert!(!cidr.contains(ip("192.168.2.1")));
+        // v4 mapped v6 addresses from dual stack sockets
+        assert!(cidr.contains(ip("::ffff:192.168.1.42")));
+    }
+
+    #[test]
+    fn matches_v6_blocks() {
+        let cidr = Cidr::parse("fd00::/8").unwrap();
+        assert!(cidr.contains(ip("fd12:3456::1")));
+        assert!(!cidr.contains(ip("fe80::1")));
+        assert!(!cidr.contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn zero_prefix_matches_everything_in_its_family() {
+        let v4 = Cidr::parse("0.0.0.0/0").unwrap();
+        assert!(v4.contains(ip("8.8.8.8")));
+        assert!(!v4.contains(ip("2001:db8::1")));
+        let v6 = Cidr::parse("::/0").unwrap();
+        assert!(v6.contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn full_prefix_and_bare_addresses_match_one_host() {
+        let cidr = Cidr::parse("10.0.0.5/32").unwrap();
+        assert_eq!(cidr, Cidr::parse(" 10.0.0.5 ").unwrap());
+        assert!(cidr.contains(ip("10.0.0.5")));
+        assert!(!cidr.contains(ip("10.0.0.6")));
+        let cidr = Cidr::parse("2001:db8::1/128").unwrap();
+        assert!(cidr.contains(ip("2001:db8::1")));
+        assert!(!cidr.contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        for value in [
+            "",
+            "not-an-ip",
+            "10.0.0/24",
+            "10.0.0.0/33",
+            "10.0.0.0/-1",
+            "10.0.0.0/",
+            "fd00::/129",
+        ] {
+            assert!(Cidr::parse(value).is_err(), "{} should not parse", value);
+        }
+    }
+
+    #[test]
+    fn deny_rules_win_and_allow_rules_restrict() {
+        let open = AccessList::from_rules(&[]);
+        assert!(open.is_allowed(ip("203.0.113.9")));
+
+        let list = AccessList::from_rules(&[
+            rule("10.0.0.0/8", AccessAction::Allow),
+            rule("10.0.0.13", AccessAction::Deny),
+            rule("bogus", AccessAction::Deny),
+        ]);
+        assert!(list.is_allowed(ip("10.1.2.3")));
+        assert!(!list.is_allowed(ip("10.0.0.13")));
+        assert!(!list.is_allowed(ip("192.168.0.1")));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{
+    commands,
+    context::AppContext,
+    events::EventReceiver,
+    rtmp::{SourceInfo, SourceStats},
+};
+use futures_util::SinkExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::atomic::Ordering};
+use tokio::sync::broadcast::error::RecvError;
+use warp::{
+    http::StatusCode,
+    reject::Reject,
+    reply::{self, Response},
+    ws::{Message, WebSocket, Ws},
+    Filter, Rejection, Reply,
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Browsers can't set headers on a WebSocket, so the key may also come as a query parameter
+const API_KEY_QUERY: &str = "api_key";
+
+#[derive(Debug)]
+struct ApiUnauthorized;
+
+impl Reject for ApiUnauthorized {}
+
+#[derive(Debug, Serialize)]
+struct ApiError {
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupQuery {
+    group_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// Comma separated event names, all events when missing
+    events: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamStatus {
+    pub ready: bool,
+    pub stream_active: bool,
+    pub source: Option<SourceInfo>,
+    pub stats: Option<SourceStats>,
+    pub running_relays: Vec<i64>,
+}
+
+fn json<T: Serialize>(result: Result<T, String>) -> Response {
+    match result {
+        Ok(value) => reply::json(&value).into_response(),
+        Err(error) => reply::with_status(reply::json(&ApiError { error }), StatusCode::BAD_REQUEST)
+            .into_response(),
+    }
+}
+
+async fn stream_status(app: &AppContext) -> Result<StreamStatus, String> {
+    let state = app.state();
+    let mut running_relays: Vec<i64> = state.relays.lock().await.keys().copied().collect();
+    running_relays.sort();
+    Ok(StreamStatus {
+        ready: state.is_ready(),
+        stream_active: state.source_active.load(Ordering::SeqCst),
+        source: state.source_info.lock().await.clone(),
+        stats: state.source_stats.lock().await.clone(),
+        running_relays,
+    })
+}
+
+/// Compares in constant time, so response timing doesn't give the key away
+/// byte by byte. Comparing digests also hides the key's length.
+fn key_matches(given: &str, expected: &str) -> bool {
+    let given = Sha256::digest(given.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Passes only while the API is enabled and the request carries the key as a
+/// bearer token, in `x-api-key` or as `api_key` in the query. A disabled API
+/// looks like it isn't there.
+fn authorize(app: AppContext) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>(API_KEY_HEADER))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |authorization: Option<String>,
+                  key: Option<String>,
+                  query: HashMap<String, String>| {
+                let app = app.clone();
+                async move {
+                    let settings = app.state().api_settings.lock().await.clone();
+                    if !settings.enabled {
+                        return Err(warp::reject::not_found());
+                    }
+                    let bearer = authorization
+                        .as_deref()
+                        .and_then(|value| value.strip_prefix("Bearer "))
+                        .map(str::trim);
+                    let key = bearer
+                        .or(key.as_deref())
+                        .or(query.get(API_KEY_QUERY).map(String::as_str));
+                    if key.is_some_and(|key| key_matches(key, &settings.api_key)) {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::custom(ApiUnauthorized))
+                    }
+                }
+            },
+        )
+        .untuple_one()
+}
+
+async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<ApiUnauthorized>().is_some() {
+        let error = ApiError {
+            error: "Missing or invalid API key".into(),
+        };
+        return Ok(reply::with_status(
+            reply::json(&error),
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+    Err(rejection)
+}
+
+/// Sends every bus event the client asked for as a JSON text message.
+async fn stream_events(mut socket: WebSocket, mut rx: EventReceiver, filter: Option<Vec<String>>) {
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                eprintln!("⚠️ Event stream client missed {} events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if filter
+            .as_ref()
+            .is_some_and(|names| !names.iter().any(|name| name == event.event))
+        {
+            continue;
+        }
+        let Ok(text) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(Message::text(text)).await.is_err() {
+            return;
+        }
+    }
+    let _ = socket.close().await;
+}
+
+/// JSON control API under `/api/v1`, doing what the matching Tauri commands do.
+pub fn api_routes(
+    app: AppContext,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let with_app = {
+        let app = app.clone();
+        warp::any().map(move || app.clone())
+    };
+    let status = warp::path!("status")
+        .and(warp::get())
+        .and(with_app.clone())
+        .then(|app: AppContext| async move { json(stream_status(&app).await) });
+    let stats = warp::path!("stats")
+        .and(warp::get())
+        .and(with_app.clone())
+        .then(|app: AppContext| async move { json(commands::get_source_stats(&app).await) });
+    let targets = warp::path!("targets")
+        .and(warp::get())
+        .then(|| async { json(commands::get_relay_targets().await) });
+    let encoder = warp::path!("encoder")
+        .and(warp::get())
+        .and(with_app.clone())
+        .then(|app: AppContext| async move { json(commands::get_encoder_settings(&app).await) });
+    let start_all = warp::path!("relays" / "start")
+        .and(warp::post())
+        .and(warp::query::<GroupQuery>())
+        .and(with_app.clone())
+        .then(|query: GroupQuery, app: AppContext| async move {
+            json(commands::start_all_relays(&app, query.group_id).await)
+        });
+    let stop_all = warp::path!("relays" / "stop")
+        .and(warp::post())
+        .and(warp::query::<GroupQuery>())
+        .and(with_app.clone())
+        .then(|query: GroupQuery, app: AppContext| async move {
+            json(commands::stop_all_relays(&app, query.group_id).await)
+        });
+    let start_one = warp::path!("relays" / i64 / "start")
+        .and(warp::post())
+        .and(with_app.clone())
+        .then(
+            |id: i64, app: AppContext| async move { json(commands::start_relay(&app, id).await) },
+        );
+    let stop_one = warp::path!("relays" / i64 / "stop")
+        .and(warp::post())
+        .and(with_app.clone())
+        .then(|id: i64, app: AppContext| async move { json(commands::stop_relay(&app, id).await) });
+    let events = warp::path!("events")
+        .and(warp::ws())
+        .and(warp::query::<EventsQuery>())
+        .and(with_app)
+        .map(|ws: Ws, query: EventsQuery, app: AppContext| {
+            // subscribe before the upgrade so nothing published meanwhile is lost
+            let rx = app.state().events.subscribe();
+            let filter = query.events.map(|events| {
+                events
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            });
+            ws.on_upgrade(move |socket| stream_events(socket, rx, filter))
+        });
+
+    warp::path!("api" / "v1" / ..)
+        .and(authorize(app))
+        .and(
+            status
+                .or(stats)
+                .or(targets)
+                .or(encoder)
+                .or(start_all)
+                .or(stop_all)
+                .or(start_one)
+                .or(stop_one)
+                .or(events),
+        )
+        .recover(handle_rejection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_only_the_exact_key() {
+        assert!(key_matches("secret-key", "secret-key"));
+        assert!(!key_matches("secret-kez", "secret-key"));
+        assert!(!key_matches("secret", "secret-key"));
+        assert!(!key_matches("", "secret-key"));
+    }
+}
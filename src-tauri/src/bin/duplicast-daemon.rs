@@ -0,0 +1,3 @@
+fn main() {
+    duplicast_lib::run_daemon()
+}
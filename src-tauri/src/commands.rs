@@ -0,0 +1,59 @@
+use crate::{
+    context::AppContext,
+    db::{self, EncoderSettings, RelayTargetPublic},
+    rtmp::{relay, SourceStats},
+};
+
+// What the Tauri commands and the HTTP API both do, so the two can't drift apart.
+
+pub async fn get_source_stats(app: &AppContext) -> Result<Option<SourceStats>, String> {
+    Ok(app.state().source_stats.lock().await.clone())
+}
+
+pub async fn get_relay_targets() -> Result<Vec<RelayTargetPublic>, String> {
+    let targets = db::get_relay_targets(db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(targets
+        .iter()
+        .map(RelayTargetPublic::from_relay_target)
+        .collect())
+}
+
+pub async fn get_encoder_settings(app: &AppContext) -> Result<EncoderSettings, String> {
+    Ok(app.state().encoder_settings.lock().await.clone())
+}
+
+/// Starts the relays of a group, or every enabled relay without one.
+pub async fn start_all_relays(app: &AppContext, group_id: Option<i64>) -> Result<(), String> {
+    match group_id {
+        Some(group_id) => relay::start_relay_group(app, group_id)
+            .await
+            .map_err(|e| e.to_string())?,
+        None => relay::start_relays(app).await,
+    }
+    Ok(())
+}
+
+pub async fn stop_all_relays(app: &AppContext, group_id: Option<i64>) -> Result<(), String> {
+    match group_id {
+        Some(group_id) => relay::stop_relay_group(app, group_id)
+            .await
+            .map_err(|e| e.to_string())?,
+        None => relay::stop_relays(app).await,
+    }
+    Ok(())
+}
+
+pub async fn start_relay(app: &AppContext, id: i64) -> Result<(), String> {
+    let target = db::get_relay_target(id, db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    relay::start_relay(app, &target).await;
+    Ok(())
+}
+
+pub async fn stop_relay(app: &AppContext, id: i64) -> Result<(), String> {
+    relay::stop_relay(app, id).await;
+    Ok(())
+}
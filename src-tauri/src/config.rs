@@ -1,25 +1,42 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
+use bytes::Bytes;
 use get_if_addrs::get_if_addrs;
 use rml_rtmp::sessions::StreamMetadata;
 use serde::Serialize;
 use sqlx::{prelude::FromRow, SqlitePool};
-use tauri::{AppHandle, Manager};
 use tokio::{
     net::TcpListener,
     process::{Child, ChildStdin},
     sync::{mpsc, Mutex},
     task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::db::{self, EncoderSettings};
+use crate::{
+    access::AccessList,
+    context::AppContext,
+    db::{
+        self, ApiSettings, BindMode, EncoderSettings, IngestSettings, OverlaySettings,
+        PreviewAccessSettings, PreviewMode,
+    },
+    dvr::Dvr,
+    events::EventBus,
+    llhls::LlHls,
+    metrics::Metrics,
+    redact::Redactor,
+    rtmp::{FlvPreview, SourceInfo, SourceStats},
+    viewers::Viewers,
+};
 
 #[derive(Debug, Clone, Serialize, FromRow)]
 pub struct PortInfo {
@@ -31,6 +48,23 @@ pub struct PortInfo {
 pub struct StartUpData {
     pub ports: PortInfo,
     pub ips: Vec<String>,
+    pub port_fallbacks: Vec<PortFallback>,
+}
+
+/// A stored port that was taken at startup and replaced with a free one.
+#[derive(Debug, Clone, Serialize)]
+pub struct PortFallback {
+    pub service: &'static str,
+    pub requested: u16,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestStatus {
+    /// Address the RTMP server is listening on, None until it is bound
+    pub listen_address: Option<String>,
+    /// Connections turned away by the access rules, a ban or a busy ingest
+    pub rejected_connections: u64,
 }
 
 #[derive(Debug)]
@@ -38,44 +72,152 @@ pub struct AppState {
     pub rtmp_ready: Arc<AtomicBool>,
     pub file_ready: Arc<AtomicBool>,
     pub rtmp_active: AtomicBool,
+    pub rtmp_listener: Mutex<Option<ListenerHandle>>,
+    pub file_server: Mutex<Option<ListenerHandle>>,
+    pub port_fallbacks: Mutex<Vec<PortFallback>>,
+    pub access_list: Mutex<AccessList>,
+    pub rejected_connections: AtomicU64,
+    pub source_connection: Mutex<Option<SourceConnection>>,
+    /// Publisher addresses refused until the given time
+    pub banned_ips: Mutex<HashMap<IpAddr, Instant>>,
     pub source_active: Arc<AtomicBool>,
     pub source_metadata: Mutex<Option<StreamMetadata>>,
+    pub source_stats: Mutex<Option<SourceStats>>,
+    pub source_info: Mutex<Option<SourceInfo>>,
     pub ports: Arc<Mutex<PortInfo>>,
     pub relays: Mutex<HashMap<i64, RelayHandle>>,
+    /// Relays whose last run ended in an error, until they start again
+    pub failed_relays: Mutex<HashSet<i64>>,
     pub relay_channels: Mutex<HashMap<i64, mpsc::Sender<Arc<Vec<u8>>>>>,
+    pub renditions: Mutex<HashMap<String, RenditionHandle>>,
+    /// Non-relay consumers of the program feed (meters, previews), fed whole FLV tags
+    /// without the file header. Slow taps drop tags.
+    pub feed_taps: Mutex<HashMap<String, mpsc::Sender<Arc<Vec<u8>>>>>,
+    pub meter: Mutex<Option<MeterHandle>>,
+    pub flv_preview: Mutex<Option<PreviewHandle>>,
+    pub thumbnailer: Mutex<Option<ThumbnailerHandle>>,
+    /// Latest JPEG thumbnail of the program feed
+    pub thumbnail: Mutex<Option<Thumbnail>>,
     pub encoder_process: Mutex<Option<Child>>,
     pub encoder_stdin: Mutex<Option<ChildStdin>>,
     pub encoder_sequence_headers: Mutex<Vec<Vec<u8>>>,
     pub encoder_settings: Mutex<EncoderSettings>,
+    pub ll_hls: Arc<LlHls>,
+    pub dvr: Arc<Dvr>,
+    pub overlay_settings: Mutex<OverlaySettings>,
+    pub ingest_settings: Mutex<IngestSettings>,
+    pub preview_access: Mutex<PreviewAccessSettings>,
+    pub api_settings: Mutex<ApiSettings>,
+    pub events: EventBus,
+    pub viewers: Viewers,
+    pub metrics: Metrics,
+    pub redactor: Arc<Redactor>,
     // pub metadata:
 }
 
+#[derive(Debug)]
+pub struct ListenerHandle {
+    pub addr: SocketAddr,
+    pub task: JoinHandle<()>,
+}
+
+/// The publisher currently holding the ingest, cancelling the token closes its session.
+#[derive(Debug, Clone)]
+pub struct SourceConnection {
+    pub addr: SocketAddr,
+    pub cancel: CancellationToken,
+}
+
 #[derive(Debug)]
 pub struct RelayHandle {
     pub id: i64,
     pub process: Arc<Mutex<Child>>,
     pub rx_task: JoinHandle<()>,
+    /// Set when the relay is stopped on purpose so its exit is not reported as a failure
+    pub stopped: Arc<AtomicBool>,
     // pub tx: mpsc::Sender<Arc<Vec<u8>>>,
 }
 
+/// A transformed re-encode of the program feed, shared by every relay using the same filter.
+#[derive(Debug)]
+pub struct RenditionHandle {
+    pub process: Arc<Mutex<Child>>,
+    pub input: mpsc::Sender<Arc<Vec<u8>>>,
+    pub input_task: JoinHandle<()>,
+    pub fanout_task: JoinHandle<()>,
+    pub sequence_headers: Arc<Mutex<Vec<Vec<u8>>>>,
+    pub channels: Arc<Mutex<HashMap<i64, mpsc::Sender<Arc<Vec<u8>>>>>>,
+}
+
+#[derive(Debug)]
+pub struct MeterHandle {
+    pub process: Child,
+    pub tasks: Vec<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+pub struct ThumbnailerHandle {
+    pub process: Child,
+    pub tasks: Vec<JoinHandle<()>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub jpeg: Bytes,
+    /// Unix milliseconds
+    pub captured_at: u64,
+}
+
+#[derive(Debug)]
+pub struct PreviewHandle {
+    pub preview: Arc<FlvPreview>,
+    pub task: JoinHandle<()>,
+}
+
 impl AppState {
     pub fn new(rtmp_port: u16, file_port: u16) -> Self {
         Self {
             rtmp_ready: Arc::new(AtomicBool::new(false)),
             source_active: Arc::new(AtomicBool::new(false)),
             rtmp_active: AtomicBool::new(false),
+            rtmp_listener: Mutex::new(None),
+            file_server: Mutex::new(None),
+            port_fallbacks: Mutex::new(vec![]),
+            access_list: Mutex::new(AccessList::default()),
+            rejected_connections: AtomicU64::new(0),
+            source_connection: Mutex::new(None),
+            banned_ips: Mutex::new(HashMap::new()),
             file_ready: Arc::new(AtomicBool::new(false)),
             ports: Arc::new(Mutex::new(PortInfo {
                 rtmp_port,
                 file_port,
             })),
             relays: Mutex::new(HashMap::new()),
+            failed_relays: Mutex::new(HashSet::new()),
             encoder_process: Mutex::new(None),
             encoder_stdin: Mutex::new(None),
             relay_channels: Mutex::new(HashMap::new()),
+            renditions: Mutex::new(HashMap::new()),
+            feed_taps: Mutex::new(HashMap::new()),
+            meter: Mutex::new(None),
+            flv_preview: Mutex::new(None),
+            thumbnailer: Mutex::new(None),
+            thumbnail: Mutex::new(None),
             source_metadata: Mutex::new(None),
+            source_stats: Mutex::new(None),
+            source_info: Mutex::new(None),
             encoder_sequence_headers: Mutex::new(vec![]),
             encoder_settings: Mutex::new(db::default_encoder_settings()),
+            ll_hls: Arc::new(LlHls::new()),
+            dvr: Arc::new(Dvr::new()),
+            overlay_settings: Mutex::new(db::default_overlay_settings()),
+            ingest_settings: Mutex::new(db::default_ingest_settings()),
+            preview_access: Mutex::new(db::default_preview_access_settings()),
+            api_settings: Mutex::new(db::default_api_settings()),
+            events: EventBus::new(),
+            viewers: Viewers::default(),
+            metrics: Metrics::new(),
+            redactor: Arc::new(Redactor::new()),
         }
     }
 
@@ -92,15 +234,42 @@ impl AppState {
         let mut relay_channels = self.relay_channels.lock().await;
         relay_channels.remove(&id);
     }
+
+    pub async fn register_feed_tap(&self, name: &str, tx: mpsc::Sender<Arc<Vec<u8>>>) {
+        self.feed_taps.lock().await.insert(name.to_string(), tx);
+    }
+
+    pub async fn unregister_feed_tap(&self, name: &str) {
+        self.feed_taps.lock().await.remove(name);
+    }
+
+    pub async fn reload_access_rules(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let rules = db::get_access_rules(pool).await?;
+        *self.access_list.lock().await = AccessList::from_rules(&rules);
+        Ok(())
+    }
+
+    pub async fn ban_ip(&self, ip: IpAddr, duration: Duration) {
+        self.banned_ips
+            .lock()
+            .await
+            .insert(ip, Instant::now() + duration);
+    }
+
+    pub async fn is_banned(&self, ip: IpAddr) -> bool {
+        let mut banned_ips = self.banned_ips.lock().await;
+        banned_ips.retain(|_, until| *until > Instant::now());
+        banned_ips.contains_key(&ip)
+    }
 }
 
-async fn find_available_port(start_port: u16) -> Result<u16, Box<dyn std::error::Error>> {
+pub async fn find_available_port(start_port: u16) -> Result<u16, Box<dyn std::error::Error>> {
     for port in start_port..=65535 {
-        if TcpListener::bind(("127.0.0.1", port)).await.is_ok() {
+        if is_port_available(port).await {
             return Ok(port);
         }
     }
-    panic!("⚠️ No available ports found");
+    Err(format!("No available ports found from {}", start_port).into())
 }
 
 pub async fn get_ip_addresses() -> Vec<String> {
@@ -119,6 +288,32 @@ pub async fn get_ip_addresses() -> Vec<String> {
     ips
 }
 
+/// Resolves the address the ingest server should listen on.
+pub fn ingest_bind_ip(settings: &IngestSettings) -> Result<IpAddr, String> {
+    match settings.bind_mode {
+        BindMode::Loopback => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        BindMode::All => Ok(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        BindMode::Interface => {
+            let address = settings
+                .bind_address
+                .as_deref()
+                .ok_or("No interface address set")?;
+            let ip: IpAddr = address
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid bind address '{}'", address))?;
+            let is_local = get_if_addrs()
+                .map_err(|e| e.to_string())?
+                .iter()
+                .any(|iface| iface.ip() == ip);
+            if !is_local {
+                return Err(format!("{} is not an address of this machine", ip));
+            }
+            Ok(ip)
+        }
+    }
+}
+
 pub async fn get_or_init_ports(pool: &SqlitePool) -> Result<PortInfo, Box<dyn std::error::Error>> {
     // Try reading existing config
     if let Some(config) =
@@ -145,30 +340,216 @@ pub async fn get_or_init_ports(pool: &SqlitePool) -> Result<PortInfo, Box<dyn st
     })
 }
 
+pub async fn is_port_available(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .await
+        .is_ok()
+}
+
+/// Swaps any stored port another program has taken for a free one and saves the result.
+pub async fn ensure_ports_available(
+    ports: PortInfo,
+    pool: &SqlitePool,
+) -> Result<(PortInfo, Vec<PortFallback>), Box<dyn std::error::Error>> {
+    let mut resolved = ports.clone();
+    let mut fallbacks = vec![];
+    if !is_port_available(ports.rtmp_port).await {
+        resolved.rtmp_port = find_available_port(ports.rtmp_port).await?;
+        fallbacks.push(PortFallback {
+            service: "rtmp",
+            requested: ports.rtmp_port,
+            port: resolved.rtmp_port,
+        });
+    }
+    if !is_port_available(ports.file_port).await {
+        let mut file_port = find_available_port(ports.file_port).await?;
+        if file_port == resolved.rtmp_port {
+            let next = file_port.checked_add(1).ok_or("No available ports found")?;
+            file_port = find_available_port(next).await?;
+        }
+        resolved.file_port = file_port;
+        fallbacks.push(PortFallback {
+            service: "file",
+            requested: ports.file_port,
+            port: resolved.file_port,
+        });
+    }
+    if !fallbacks.is_empty() {
+        save_ports(&resolved, pool).await?;
+    }
+    Ok((resolved, fallbacks))
+}
+
+pub async fn save_ports(ports: &PortInfo, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE port_config SET rtmp_port = ?, file_port = ?")
+        .bind(ports.rtmp_port)
+        .bind(ports.file_port)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 // store preview output path
 
-pub fn get_data_dir(app: &AppHandle) -> PathBuf {
-    let data_dir = app
-        .path()
-        .app_local_data_dir()
-        .unwrap_or_else(|_| std::env::current_dir().unwrap());
-    data_dir
+pub fn get_data_dir(app: &AppContext) -> PathBuf {
+    app.paths().data_dir.clone()
 }
-pub fn hls_output_dir(app: &AppHandle) -> PathBuf {
+pub fn hls_output_dir(app: &AppContext) -> PathBuf {
     get_data_dir(app).join("./hls_output")
 }
-pub fn log_output_dir(app: &AppHandle) -> PathBuf {
+pub fn log_output_dir(app: &AppContext) -> PathBuf {
     get_data_dir(app).join("./logs")
 }
-pub fn hls_playlist_path(app: &AppHandle) -> PathBuf {
+pub fn hls_playlist_path(app: &AppContext) -> PathBuf {
     hls_output_dir(app).join("playlist.m3u8")
 }
+pub fn ll_hls_dir(app: &AppContext) -> PathBuf {
+    hls_output_dir(app).join("ll")
+}
+/// ffmpeg's own playlist of LL-HLS parts, the served playlist is built from it
+pub fn ll_hls_parts_path(app: &AppContext) -> PathBuf {
+    ll_hls_dir(app).join("parts.m3u8")
+}
+/// The file ffmpeg writes once the preview in `mode` can be played
+pub fn preview_ready_path(app: &AppContext, mode: PreviewMode) -> PathBuf {
+    match mode {
+        PreviewMode::Hls => hls_playlist_path(app),
+        PreviewMode::LlHls => ll_hls_parts_path(app),
+    }
+}
+/// Where captured snapshots go unless the user picks a folder
+pub fn snapshot_dir(app: &AppContext) -> PathBuf {
+    app.paths().snapshot_dir.clone()
+}
+pub fn overlay_text_path(app: &AppContext) -> PathBuf {
+    get_data_dir(app).join("overlay_text.txt")
+}
+
+/// Writes the live overlay text. ffmpeg re-reads the file every frame, so it is
+/// replaced atomically to avoid rendering a half written line.
+pub fn write_overlay_text(app: &AppContext, text: &str) -> std::io::Result<()> {
+    let path = overlay_text_path(app);
+    let tmp_path = path.with_extension("txt.tmp");
+    std::fs::write(&tmp_path, text)?;
+    std::fs::rename(tmp_path, path)
+}
 
 pub fn mask_key(key: &str) -> String {
-    if key.len() <= 4 {
-        "*".repeat(key.len())
+    let len = key.chars().count();
+    if len <= 4 {
+        "*".repeat(len)
     } else {
-        let visible = &key[key.len() - 4..];
-        format!("{}{}", "*".repeat(key.len() - 4), visible)
+        let visible: String = key.chars().skip(len - 4).collect();
+        format!("{}{}", "*".repeat(len - 4), visible)
+    }
+}
+
+const RELAY_URL_SCHEMES: [&str; 2] = ["rtmp", "rtmps"];
+
+/// Checks that a relay url looks like `rtmp(s)://host[:port]/path` and returns it
+/// without surrounding whitespace or a trailing slash.
+pub fn validate_relay_url(url: &str) -> Result<String, String> {
+    let url = url.trim().trim_end_matches('/');
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| format!("Invalid url '{}': missing scheme", url))?;
+    if !RELAY_URL_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) {
+        return Err(format!(
+            "Unsupported url scheme '{}', expected one of {}",
+            scheme,
+            RELAY_URL_SCHEMES.join(", ")
+        ));
+    }
+    let authority = rest.split('/').next().unwrap_or_default();
+    let (host, port) = match authority.strip_prefix('[') {
+        // ipv6 literal, e.g. [::1]:1935
+        Some(inner) => match inner.split_once(']') {
+            Some((host, tail)) => (host, tail.strip_prefix(':')),
+            None => ("", None),
+        },
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let valid_host = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'));
+    if !valid_host {
+        return Err(format!("Invalid host in url '{}'", url));
+    }
+    if let Some(port) = port {
+        if !matches!(port.parse::<u16>(), Ok(p) if p != 0) {
+            return Err(format!("Invalid port in url '{}'", url));
+        }
+    }
+    Ok(url.to_string())
+}
+
+pub fn validate_stream_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("Stream key cannot be empty".into());
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_all_but_the_last_four_characters() {
+        assert_eq!(mask_key("live_abcd1234"), "*********1234");
+        assert_eq!(mask_key("abcd"), "****");
+        assert_eq!(mask_key(""), "");
+    }
+
+    #[test]
+    fn masks_non_ascii_keys_by_character() {
+        assert_eq!(mask_key("clé_sécrète"), "*******rète");
+        assert_eq!(mask_key("ключ"), "****");
+    }
+
+    #[test]
+    fn accepts_rtmp_and_rtmps_urls() {
+        assert_eq!(
+            validate_relay_url("rtmp://live.twitch.tv/app").unwrap(),
+            "rtmp://live.twitch.tv/app"
+        );
+        assert_eq!(
+            validate_relay_url(" rtmps://live-api-s.facebook.com:443/rtmp/ ").unwrap(),
+            "rtmps://live-api-s.facebook.com:443/rtmp"
+        );
+        assert!(validate_relay_url("RTMP://[::1]:1935/live").is_ok());
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        assert!(validate_relay_url("http://live.twitch.tv/app").is_err());
+        assert!(validate_relay_url("srt://live.twitch.tv:9000").is_err());
+        assert!(validate_relay_url("live.twitch.tv/app").is_err());
+    }
+
+    #[test]
+    fn rejects_urls_without_a_host() {
+        assert!(validate_relay_url("rtmp:///app").is_err());
+        assert!(validate_relay_url("rtmp://:1935/app").is_err());
+        assert!(validate_relay_url("rtmp://bad host/app").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_ports() {
+        assert!(validate_relay_url("rtmp://example.com:0/app").is_err());
+        assert!(validate_relay_url("rtmp://example.com:65536/app").is_err());
+        assert!(validate_relay_url("rtmp://example.com:abc/app").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_stream_keys() {
+        assert!(validate_stream_key("").is_err());
+        assert!(validate_stream_key("   ").is_err());
+        assert_eq!(validate_stream_key(" live_abc ").unwrap(), "live_abc");
     }
 }
@@ -0,0 +1,77 @@
+use crate::{
+    config::AppState,
+    events::{AppEvents, EventPublisher},
+};
+use serde::Serialize;
+use std::{path::PathBuf, sync::Arc};
+#[cfg(feature = "desktop")]
+use tauri::{AppHandle, Manager};
+
+/// Where the pipeline keeps its files.
+#[derive(Debug, Clone)]
+pub struct AppPaths {
+    /// The database, logs and preview output live under here
+    pub data_dir: PathBuf,
+    /// Where captured snapshots go unless the user picks a folder
+    pub snapshot_dir: PathBuf,
+}
+
+impl AppPaths {
+    /// Everything under one directory, as the daemon runs.
+    pub fn in_dir(data_dir: PathBuf) -> Self {
+        Self {
+            snapshot_dir: data_dir.join("snapshots"),
+            data_dir,
+        }
+    }
+
+    /// The platform's app directories for the desktop app.
+    #[cfg(feature = "desktop")]
+    pub fn resolve(app: &AppHandle) -> Self {
+        let data_dir = app
+            .path()
+            .app_local_data_dir()
+            .unwrap_or_else(|_| std::env::current_dir().unwrap());
+        let snapshot_dir = app
+            .path()
+            .picture_dir()
+            .map(|dir| dir.join("duplicast"))
+            .unwrap_or_else(|_| data_dir.join("snapshots"));
+        Self {
+            data_dir,
+            snapshot_dir,
+        }
+    }
+}
+
+/// What the RTMP pipeline, file server and API need from whoever hosts them:
+/// the shared state, the file locations and the event bus. The desktop app and
+/// the headless daemon each build one. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct AppContext {
+    state: Arc<AppState>,
+    paths: Arc<AppPaths>,
+}
+
+impl AppContext {
+    pub fn new(state: Arc<AppState>, paths: AppPaths) -> Self {
+        Self {
+            state,
+            paths: Arc::new(paths),
+        }
+    }
+
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    pub fn paths(&self) -> &AppPaths {
+        &self.paths
+    }
+}
+
+impl EventPublisher for AppContext {
+    fn publish<S: Serialize>(&self, event: AppEvents, payload: S) {
+        self.state.events.publish(event, payload);
+    }
+}
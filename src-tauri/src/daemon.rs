@@ -0,0 +1,268 @@
+use crate::{
+    config::{self, AppState},
+    context::{AppContext, AppPaths},
+    db,
+    events::AppEvents,
+    pipeline,
+    rtmp::{self, relay},
+};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::broadcast::error::RecvError;
+
+const DEFAULT_CONFIG_PATH: &str = "duplicast.toml";
+
+/// Settings of the headless daemon, read from a TOML file. Everything else
+/// comes from the SQLite database in `data_dir`, shared with the desktop app.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DaemonConfig {
+    /// Holds the database, logs and preview output. Defaults to the working directory.
+    data_dir: Option<PathBuf>,
+    /// Replace the stored ports
+    rtmp_port: Option<u16>,
+    file_port: Option<u16>,
+    /// Serve the preview, metrics and API beyond this machine
+    lan_access: Option<bool>,
+    /// Key for the HTTP/WebSocket API instead of the stored one. Without it the
+    /// API takes the key generated on the first run, kept in the `api_settings`
+    /// table of `app.sqlite` in `data_dir`. The daemon prints that path on startup.
+    api_key: Option<String>,
+    /// Start every enabled relay once the encoder output is ready
+    auto_start_relays: bool,
+}
+
+impl DaemonConfig {
+    /// A missing file is fine unless it was asked for by name.
+    fn load(path: &Path, required: bool) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| format!("Invalid config {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                println!("📄 No {} found, using defaults", path.display());
+                Ok(Self::default())
+            }
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// `--config <path>`, or the default file in the working directory.
+fn config_path(args: impl IntoIterator<Item = String>) -> (PathBuf, bool) {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" || arg == "-c" {
+            if let Some(path) = args.next() {
+                return (PathBuf::from(path), true);
+            }
+        }
+    }
+    (PathBuf::from(DEFAULT_CONFIG_PATH), false)
+}
+
+async fn apply_port_overrides(config: &DaemonConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if config.rtmp_port.is_none() && config.file_port.is_none() {
+        return Ok(());
+    }
+    let pool = db::get_db_pool();
+    let mut ports = config::get_or_init_ports(pool).await?;
+    ports.rtmp_port = config.rtmp_port.unwrap_or(ports.rtmp_port);
+    ports.file_port = config.file_port.unwrap_or(ports.file_port);
+    config::save_ports(&ports, pool).await?;
+    Ok(())
+}
+
+/// The daemon has no other way to be controlled, so the API is always on.
+async fn apply_access_overrides(app: &AppContext, config: &DaemonConfig) {
+    let state = app.state();
+    let mut api_settings = state.api_settings.lock().await;
+    api_settings.enabled = true;
+    match config.api_key.as_ref().filter(|key| !key.is_empty()) {
+        Some(api_key) => {
+            api_settings.api_key = api_key.clone();
+            println!("🔑 Using the API key from the config file");
+        }
+        None => println!(
+            "🔑 Using the stored API key, found in the api_settings table of {}. Set api_key in the config to choose one",
+            db::database_file(&app.paths().data_dir).display()
+        ),
+    }
+    if let Some(lan_access) = config.lan_access {
+        state.preview_access.lock().await.lan_access = lan_access;
+    }
+}
+
+/// Starts the enabled relays each time the encoder output becomes playable,
+/// which is also when the sequence headers relays begin with are known.
+fn auto_start_relays(app: &AppContext) {
+    let mut rx = app.state().events.subscribe();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) if event.event == AppEvents::StreamPreviewActive.as_str() => {
+                    println!("🚀 Stream is live, starting relays");
+                    relay::start_relays(&app).await;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    let ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+    let terminate = std::pin::pin!(terminate.recv());
+    futures_util::future::select(ctrl_c, terminate).await;
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+async fn run(config: DaemonConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = match &config.data_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir()?,
+    };
+    std::fs::create_dir_all(&data_dir)?;
+    let app = AppContext::new(
+        Arc::new(AppState::new(0, 0)),
+        AppPaths::in_dir(data_dir.clone()),
+    );
+    pipeline::prepare_dirs(&app);
+    db::init_db(&data_dir).await?;
+    println!("✅ Database ready");
+    pipeline::start_services(&app);
+    apply_port_overrides(&config).await?;
+    let ports = pipeline::load_settings(&app).await;
+    apply_access_overrides(&app, &config).await;
+    if config.auto_start_relays {
+        auto_start_relays(&app);
+    }
+    pipeline::serve(&app, &ports);
+    println!(
+        "🖥️ Duplicast daemon running, RTMP on port {} and API on port {}",
+        ports.rtmp_port, ports.file_port
+    );
+
+    shutdown_signal().await;
+    println!("🛑 Shutting down");
+    relay::stop_relays(&app).await;
+    rtmp::stop_encoder(&app).await;
+    Ok(())
+}
+
+/// Entry point of the `duplicast-daemon` binary: the RTMP server, encoder,
+/// relays and file server without the window, controlled through the API.
+pub fn run_daemon() {
+    let (path, required) = config_path(std::env::args().skip(1));
+    let config = match DaemonConfig::load(&path, required) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+    if let Err(e) = runtime.block_on(run(config)) {
+        eprintln!("❌ Daemon failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("duplicast-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn loads_every_setting() {
+        let path = write_config(
+            "full.toml",
+            r#"
+data_dir = "/var/lib/duplicast"
+rtmp_port = 1936
+file_port = 8788
+lan_access = true
+api_key = "secret"
+auto_start_relays = true
+"#,
+        );
+        let config = DaemonConfig::load(&path, true).unwrap();
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/duplicast")));
+        assert_eq!(config.rtmp_port, Some(1936));
+        assert_eq!(config.file_port, Some(8788));
+        assert_eq!(config.lan_access, Some(true));
+        assert_eq!(config.api_key.as_deref(), Some("secret"));
+        assert!(config.auto_start_relays);
+    }
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        let path = write_config("partial.toml", "rtmp_port = 1936\n");
+        let config = DaemonConfig::load(&path, true).unwrap();
+        assert_eq!(config.rtmp_port, Some(1936));
+        assert!(config.data_dir.is_none());
+        assert!(config.api_key.is_none());
+        assert!(!config.auto_start_relays);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let path = write_config("typo.toml", "rtmp_prot = 1936\n");
+        let error = DaemonConfig::load(&path, true).unwrap_err();
+        assert!(error.starts_with("Invalid config"), "{}", error);
+        assert!(error.contains("rtmp_prot"), "{}", error);
+    }
+
+    #[test]
+    fn a_missing_default_file_means_defaults() {
+        let path = std::env::temp_dir().join("duplicast-daemon-missing.toml");
+        let config = DaemonConfig::load(&path, false).unwrap();
+        assert!(config.data_dir.is_none());
+        assert!(config.rtmp_port.is_none());
+        assert!(!config.auto_start_relays);
+    }
+
+    #[test]
+    fn a_missing_named_file_is_an_error() {
+        let path = std::env::temp_dir().join("duplicast-daemon-missing.toml");
+        let error = DaemonConfig::load(&path, true).unwrap_err();
+        assert!(error.starts_with("Failed to read"), "{}", error);
+    }
+
+    #[test]
+    fn takes_the_config_path_from_the_arguments() {
+        let default = (PathBuf::from(DEFAULT_CONFIG_PATH), false);
+        assert_eq!(config_path(args(&[])), default);
+        assert_eq!(config_path(args(&["--config"])), default);
+        assert_eq!(
+            config_path(args(&["--config", "/etc/duplicast.toml"])),
+            (PathBuf::from("/etc/duplicast.toml"), true)
+        );
+        assert_eq!(
+            config_path(args(&["-c", "daemon.toml"])),
+            (PathBuf::from("daemon.toml"), true)
+        );
+    }
+}
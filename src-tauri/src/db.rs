@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{migrate::Migrator, sqlite::SqlitePoolOptions, FromRow, SqlitePool};
-use std::sync::OnceLock;
-use tauri::AppHandle;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use crate::config;
 
@@ -9,8 +11,13 @@ static DB_POOL: OnceLock<SqlitePool> = OnceLock::new();
 // Path to migrations folder (relative to project root)
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
-pub async fn init_db(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
-    let db_path = config::get_data_dir(app).join("app.sqlite");
+/// The SQLite file holding every setting, inside the data directory.
+pub fn database_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("app.sqlite")
+}
+
+pub async fn init_db(data_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let db_path = database_file(data_dir);
 
     // if let Some(parent) = Path::new(db_path.as_os_str()).parent() {
     //     fs::create_dir_all(parent).expect("❌ Failed to create DB directory");
@@ -44,10 +51,114 @@ pub struct EncoderSettings {
     pub bufsize: Option<u32>,
     pub framerate: Option<u32>,
     pub resolution: Option<String>,
-    pub use_passthrough: bool, 
+    pub use_passthrough: bool,
+    /// EBU R128 integrated loudness target in LUFS
+    #[serde(default)]
+    pub loudness_target: Option<f64>,
+    /// Fixed gain in dB
+    #[serde(default)]
+    pub audio_gain: Option<f64>,
+    #[serde(default)]
+    pub audio_limiter: bool,
+    /// Output channel count, 1 to downmix or 2 to upmix
+    #[serde(default)]
+    pub audio_channels: Option<u32>,
+    #[serde(default)]
+    pub audio_sample_rate: Option<u32>,
+    #[serde(default)]
+    pub audio_channel_map: ChannelMap,
+    #[serde(default)]
+    pub preview_mode: PreviewMode,
+    /// Seconds between live thumbnails, 0 disables them
+    #[serde(default = "default_thumbnail_interval")]
+    pub thumbnail_interval_secs: u32,
+    /// Minutes of HLS preview kept for rewinding, 0 disables the DVR
+    #[serde(default)]
+    pub dvr_window_mins: u32,
+    #[serde(default = "default_dvr_max_size")]
+    pub dvr_max_size_mb: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+fn default_thumbnail_interval() -> u32 {
+    10
+}
+
+fn default_dvr_max_size() -> u32 {
+    2048
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum PreviewMode {
+    /// Plain HLS with 6 second segments
+    #[default]
+    Hls,
+    /// Low-latency HLS with partial segments and blocking playlist reload
+    LlHls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ChannelMap {
+    #[default]
+    None,
+    /// Copy the left channel to both sides, for mono-left sources
+    LeftToBoth,
+    RightToBoth,
+}
+
+/// What a relay does with the program audio before sending it out.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AudioPolicy {
+    #[default]
+    Passthrough,
+    /// Send silence instead of the source audio
+    Mute,
+    /// Swap the audio for `audio_file`, or silence when no file is set
+    Replace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum CropMode {
+    #[default]
+    None,
+    /// Centered crop to `crop_aspect`, e.g. "9:16"
+    Center,
+    /// Fixed rectangle from `crop_x`, `crop_y`, `crop_width` and `crop_height`
+    Rect,
+}
+
+/// Per-target picture transform. Targets with a transform get their own rendition.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, FromRow)]
+pub struct OutputTransform {
+    pub crop_mode: CropMode,
+    pub crop_aspect: Option<String>,
+    pub crop_x: Option<u32>,
+    pub crop_y: Option<u32>,
+    pub crop_width: Option<u32>,
+    pub crop_height: Option<u32>,
+    pub rotate: u32,
+    pub output_width: Option<u32>,
+    pub output_height: Option<u32>,
+    pub pad: bool,
+}
+
+impl OutputTransform {
+    pub fn is_identity(&self) -> bool {
+        self.crop_mode == CropMode::None
+            && self.rotate == 0
+            && self.output_width.is_none()
+            && self.output_height.is_none()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, FromRow)]
 pub struct RelayTarget {
     pub id: i64,
     pub tag: String,
@@ -55,6 +166,29 @@ pub struct RelayTarget {
     pub url: String,
     pub enabled: bool,
     pub created_at: Option<String>,
+    pub platform: Option<String>,
+    pub audio_policy: AudioPolicy,
+    pub audio_file: Option<String>,
+    #[sqlx(flatten)]
+    pub transform: OutputTransform,
+}
+
+// Keep stream keys out of logs and console output
+impl std::fmt::Debug for RelayTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RelayTarget")
+            .field("id", &self.id)
+            .field("tag", &self.tag)
+            .field("stream_key", &config::mask_key(&self.stream_key))
+            .field("url", &self.url)
+            .field("enabled", &self.enabled)
+            .field("created_at", &self.created_at)
+            .field("platform", &self.platform)
+            .field("audio_policy", &self.audio_policy)
+            .field("audio_file", &self.audio_file)
+            .field("transform", &self.transform)
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -65,6 +199,11 @@ pub struct RelayTargetPublic {
     pub url: String,
     pub enabled: bool,
     pub created_at: Option<String>,
+    pub platform: Option<String>,
+    pub audio_policy: AudioPolicy,
+    pub audio_file: Option<String>,
+    #[sqlx(flatten)]
+    pub transform: OutputTransform,
 }
 impl RelayTargetPublic {
     pub fn from_relay_target(relay_target: &RelayTarget) -> Self {
@@ -75,6 +214,10 @@ impl RelayTargetPublic {
             url: relay_target.url.clone(),
             enabled: relay_target.enabled,
             created_at: relay_target.created_at.clone(),
+            platform: relay_target.platform.clone(),
+            audio_policy: relay_target.audio_policy,
+            audio_file: relay_target.audio_file.clone(),
+            transform: relay_target.transform.clone(),
         }
     }
 }
@@ -89,14 +232,18 @@ pub async fn add_relay_target(
     url: &str,
     stream_key: &str,
     tag: &str,
+    platform: Option<&str>,
     pool: &SqlitePool,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query("INSERT INTO relay_targets (stream_key, url, tag, enabled) VALUES (?, ?, ?, 1)")
-        .bind(stream_key)
-        .bind(url)
-        .bind(tag)
-        .execute(pool)
-        .await?;
+    sqlx::query(
+        "INSERT INTO relay_targets (stream_key, url, tag, platform, enabled) VALUES (?, ?, ?, ?, 1)",
+    )
+    .bind(stream_key)
+    .bind(url)
+    .bind(tag)
+    .bind(platform)
+    .execute(pool)
+    .await?;
     Ok(())
 }
 
@@ -119,6 +266,81 @@ pub async fn toggle_relay_target(
     Ok(())
 }
 
+pub async fn update_relay_target(
+    id: i64,
+    url: Option<&str>,
+    stream_key: Option<&str>,
+    tag: Option<&str>,
+    pool: &SqlitePool,
+) -> Result<RelayTarget, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE relay_targets SET
+            url = COALESCE(?, url),
+            stream_key = COALESCE(?, stream_key),
+            tag = COALESCE(?, tag)
+        WHERE id = ?",
+    )
+    .bind(url)
+    .bind(stream_key)
+    .bind(tag)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    get_relay_target(id, pool).await
+}
+
+pub async fn set_relay_audio_policy(
+    id: i64,
+    policy: AudioPolicy,
+    audio_file: Option<&str>,
+    pool: &SqlitePool,
+) -> Result<RelayTarget, sqlx::Error> {
+    let result =
+        sqlx::query("UPDATE relay_targets SET audio_policy = ?, audio_file = ? WHERE id = ?")
+            .bind(policy)
+            .bind(audio_file)
+            .bind(id)
+            .execute(pool)
+            .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    get_relay_target(id, pool).await
+}
+
+pub async fn set_relay_transform(
+    id: i64,
+    transform: &OutputTransform,
+    pool: &SqlitePool,
+) -> Result<RelayTarget, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE relay_targets SET
+            crop_mode = ?, crop_aspect = ?, crop_x = ?, crop_y = ?, crop_width = ?,
+            crop_height = ?, rotate = ?, output_width = ?, output_height = ?, pad = ?
+        WHERE id = ?",
+    )
+    .bind(transform.crop_mode)
+    .bind(&transform.crop_aspect)
+    .bind(transform.crop_x)
+    .bind(transform.crop_y)
+    .bind(transform.crop_width)
+    .bind(transform.crop_height)
+    .bind(transform.rotate)
+    .bind(transform.output_width)
+    .bind(transform.output_height)
+    .bind(transform.pad)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    get_relay_target(id, pool).await
+}
+
 pub async fn remove_relay_target(id: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
     sqlx::query("DELETE FROM relay_targets WHERE id = ?")
         .bind(id)
@@ -134,6 +356,135 @@ pub async fn get_relay_target(id: i64, pool: &SqlitePool) -> Result<RelayTarget,
         .await
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RelayGroup {
+    pub id: i64,
+    pub name: String,
+    pub created_at: Option<String>,
+    #[sqlx(skip)]
+    pub target_ids: Vec<i64>,
+}
+
+pub async fn get_relay_groups(pool: &SqlitePool) -> Result<Vec<RelayGroup>, sqlx::Error> {
+    let mut groups = sqlx::query_as::<_, RelayGroup>(
+        "SELECT id, name, created_at FROM relay_groups ORDER BY name",
+    )
+    .fetch_all(pool)
+    .await?;
+    let links: Vec<(i64, i64)> =
+        sqlx::query_as("SELECT group_id, target_id FROM relay_group_targets")
+            .fetch_all(pool)
+            .await?;
+    for group in groups.iter_mut() {
+        group.target_ids = links
+            .iter()
+            .filter(|(group_id, _)| *group_id == group.id)
+            .map(|(_, target_id)| *target_id)
+            .collect();
+    }
+    Ok(groups)
+}
+
+pub async fn get_relay_group_targets(
+    group_id: i64,
+    pool: &SqlitePool,
+) -> Result<Vec<RelayTarget>, sqlx::Error> {
+    sqlx::query_as::<_, RelayTarget>(
+        "SELECT t.* FROM relay_targets t
+        JOIN relay_group_targets g ON g.target_id = t.id
+        WHERE g.group_id = ?",
+    )
+    .bind(group_id)
+    .fetch_all(pool)
+    .await
+}
+
+async fn set_relay_group_targets(
+    group_id: i64,
+    target_ids: &[i64],
+    tx: &mut sqlx::SqliteConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM relay_group_targets WHERE group_id = ?")
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+    for target_id in target_ids {
+        sqlx::query(
+            "INSERT OR IGNORE INTO relay_group_targets (group_id, target_id) VALUES (?, ?)",
+        )
+        .bind(group_id)
+        .bind(target_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn add_relay_group(
+    name: &str,
+    target_ids: &[i64],
+    pool: &SqlitePool,
+) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query("INSERT INTO relay_groups (name) VALUES (?)")
+        .bind(name)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+    set_relay_group_targets(id, target_ids, &mut tx).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+pub async fn update_relay_group(
+    id: i64,
+    name: Option<&str>,
+    target_ids: Option<&[i64]>,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query("UPDATE relay_groups SET name = COALESCE(?, name) WHERE id = ?")
+        .bind(name)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    if let Some(target_ids) = target_ids {
+        set_relay_group_targets(id, target_ids, &mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_relay_group(id: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM relay_groups WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Enables every target in the group and disables all the others.
+pub async fn activate_relay_group(id: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT id FROM relay_groups WHERE id = ?")
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE relay_targets SET enabled = id IN (
+            SELECT target_id FROM relay_group_targets WHERE group_id = ?
+        )",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
 pub async fn load_encoder_settings(pool: &SqlitePool) -> Result<EncoderSettings, sqlx::Error> {
     sqlx::query_as::<_, EncoderSettings>("SELECT * FROM encoder_settings ORDER BY id DESC LIMIT 1")
         .fetch_one(pool)
@@ -147,8 +498,11 @@ pub async fn save_encoder_settings(
     sqlx::query(
         "INSERT INTO encoder_settings (
             video_bitrate, audio_bitrate, video_codec, audio_codec, preset,
-            tune, bufsize, framerate, resolution
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            tune, bufsize, framerate, resolution, use_passthrough,
+            loudness_target, audio_gain, audio_limiter, audio_channels,
+            audio_sample_rate, audio_channel_map, preview_mode, thumbnail_interval_secs,
+            dvr_window_mins, dvr_max_size_mb
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )
     .bind(settings.video_bitrate)
     .bind(settings.audio_bitrate)
@@ -159,11 +513,474 @@ pub async fn save_encoder_settings(
     .bind(settings.bufsize)
     .bind(settings.framerate)
     .bind(&settings.resolution)
+    .bind(settings.use_passthrough)
+    .bind(settings.loudness_target)
+    .bind(settings.audio_gain)
+    .bind(settings.audio_limiter)
+    .bind(settings.audio_channels)
+    .bind(settings.audio_sample_rate)
+    .bind(settings.audio_channel_map)
+    .bind(settings.preview_mode)
+    .bind(settings.thumbnail_interval_secs)
+    .bind(settings.dvr_window_mins)
+    .bind(settings.dvr_max_size_mb)
     .execute(pool)
     .await?;
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum OverlayPosition {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum OverlayTextMode {
+    /// Shows `text_content`, which can be rewritten while live
+    #[default]
+    Static,
+    /// Shows the local wall clock
+    Clock,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OverlaySettings {
+    pub watermark_enabled: bool,
+    pub watermark_path: Option<String>,
+    pub watermark_position: OverlayPosition,
+    pub watermark_opacity: f64,
+    pub text_enabled: bool,
+    pub text_mode: OverlayTextMode,
+    pub text_content: String,
+    pub text_position: OverlayPosition,
+    pub text_size: u32,
+    pub text_color: String,
+    pub font_file: Option<String>,
+}
+
+pub async fn load_overlay_settings(pool: &SqlitePool) -> Result<OverlaySettings, sqlx::Error> {
+    sqlx::query_as::<_, OverlaySettings>("SELECT * FROM overlay_settings ORDER BY id DESC LIMIT 1")
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn save_overlay_settings(
+    settings: &OverlaySettings,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO overlay_settings (
+            watermark_enabled, watermark_path, watermark_position, watermark_opacity,
+            text_enabled, text_mode, text_content, text_position, text_size, text_color, font_file
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(settings.watermark_enabled)
+    .bind(&settings.watermark_path)
+    .bind(settings.watermark_position)
+    .bind(settings.watermark_opacity)
+    .bind(settings.text_enabled)
+    .bind(settings.text_mode)
+    .bind(&settings.text_content)
+    .bind(settings.text_position)
+    .bind(settings.text_size)
+    .bind(&settings.text_color)
+    .bind(&settings.font_file)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub fn default_overlay_settings() -> OverlaySettings {
+    OverlaySettings {
+        watermark_enabled: false,
+        watermark_path: None,
+        watermark_position: OverlayPosition::TopRight,
+        watermark_opacity: 1.0,
+        text_enabled: false,
+        text_mode: OverlayTextMode::Static,
+        text_content: String::new(),
+        text_position: OverlayPosition::BottomLeft,
+        text_size: 36,
+        text_color: "white".into(),
+        font_file: None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum BindMode {
+    /// Only publishers on this machine can connect
+    Loopback,
+    /// Listen on the single address in `bind_address`
+    Interface,
+    #[default]
+    All,
+}
+
+/// Timeouts are in seconds, 0 disables a timeout.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct IngestSettings {
+    /// How long a client may take to complete the RTMP handshake
+    pub handshake_timeout_secs: u32,
+    /// How long a client may stay connected without publishing
+    pub publish_timeout_secs: u32,
+    /// How long a publisher may go without sending audio or video
+    pub idle_timeout_secs: u32,
+    #[serde(default)]
+    pub bind_mode: BindMode,
+    #[serde(default)]
+    pub bind_address: Option<String>,
+}
+
+pub async fn load_ingest_settings(pool: &SqlitePool) -> Result<IngestSettings, sqlx::Error> {
+    sqlx::query_as::<_, IngestSettings>("SELECT * FROM ingest_settings ORDER BY id DESC LIMIT 1")
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn save_ingest_settings(
+    settings: &IngestSettings,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO ingest_settings (
+            handshake_timeout_secs, publish_timeout_secs, idle_timeout_secs,
+            bind_mode, bind_address
+        ) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(settings.handshake_timeout_secs)
+    .bind(settings.publish_timeout_secs)
+    .bind(settings.idle_timeout_secs)
+    .bind(settings.bind_mode)
+    .bind(&settings.bind_address)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub fn default_ingest_settings() -> IngestSettings {
+    IngestSettings {
+        handshake_timeout_secs: 10,
+        publish_timeout_secs: 30,
+        idle_timeout_secs: 15,
+        bind_mode: BindMode::All,
+        bind_address: None,
+    }
+}
+
+/// LAN access to the preview file server. Clients other than this machine must present the token.
+#[derive(Clone, Serialize, Deserialize, FromRow)]
+pub struct PreviewAccessSettings {
+    pub lan_access: bool,
+    pub access_token: String,
+}
+
+impl std::fmt::Debug for PreviewAccessSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreviewAccessSettings")
+            .field("lan_access", &self.lan_access)
+            .field("access_token", &config::mask_key(&self.access_token))
+            .finish()
+    }
+}
+
+pub async fn load_preview_access_settings(
+    pool: &SqlitePool,
+) -> Result<PreviewAccessSettings, sqlx::Error> {
+    sqlx::query_as::<_, PreviewAccessSettings>(
+        "SELECT * FROM preview_access_settings ORDER BY id DESC LIMIT 1",
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn save_preview_access_settings(
+    settings: &PreviewAccessSettings,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO preview_access_settings (lan_access, access_token) VALUES (?, ?)")
+        .bind(settings.lan_access)
+        .bind(&settings.access_token)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub fn default_preview_access_settings() -> PreviewAccessSettings {
+    PreviewAccessSettings {
+        lan_access: false,
+        access_token: generate_access_token(),
+    }
+}
+
+/// The local HTTP control API. Every request must carry the key, even from this machine.
+#[derive(Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub api_key: String,
+}
+
+impl std::fmt::Debug for ApiSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiSettings")
+            .field("enabled", &self.enabled)
+            .field("api_key", &config::mask_key(&self.api_key))
+            .finish()
+    }
+}
+
+pub async fn load_api_settings(pool: &SqlitePool) -> Result<ApiSettings, sqlx::Error> {
+    sqlx::query_as::<_, ApiSettings>("SELECT * FROM api_settings ORDER BY id DESC LIMIT 1")
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn save_api_settings(
+    settings: &ApiSettings,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO api_settings (enabled, api_key) VALUES (?, ?)")
+        .bind(settings.enabled)
+        .bind(&settings.api_key)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub fn default_api_settings() -> ApiSettings {
+    ApiSettings {
+        enabled: false,
+        api_key: generate_access_token(),
+    }
+}
+
+pub fn generate_access_token() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AccessAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AccessRule {
+    pub id: i64,
+    pub cidr: String,
+    pub action: AccessAction,
+    pub created_at: Option<String>,
+}
+
+pub async fn get_access_rules(pool: &SqlitePool) -> Result<Vec<AccessRule>, sqlx::Error> {
+    sqlx::query_as::<_, AccessRule>("SELECT * FROM ingest_access_rules ORDER BY id")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn add_access_rule(
+    cidr: &str,
+    action: AccessAction,
+    pool: &SqlitePool,
+) -> Result<i64, sqlx::Error> {
+    let id = sqlx::query("INSERT INTO ingest_access_rules (cidr, action) VALUES (?, ?)")
+        .bind(cidr)
+        .bind(action)
+        .execute(pool)
+        .await?
+        .last_insert_rowid();
+    Ok(id)
+}
+
+pub async fn remove_access_rule(id: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM ingest_access_rules WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// An HTTP endpoint that receives a signed POST for each subscribed event.
+#[derive(Clone, Serialize, Deserialize, FromRow)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    /// Key for the HMAC-SHA256 signature on every delivery
+    pub secret: String,
+    /// Comma separated event names
+    pub events: String,
+    pub enabled: bool,
+    pub created_at: Option<String>,
+}
+
+impl std::fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhook")
+            .field("id", &self.id)
+            .field("url", &self.url)
+            .field("secret", &config::mask_key(&self.secret))
+            .field("events", &self.events)
+            .field("enabled", &self.enabled)
+            .field("created_at", &self.created_at)
+            .finish()
+    }
+}
+
+impl Webhook {
+    pub fn subscribes_to(&self, event: &str) -> bool {
+        self.events.split(',').any(|name| name.trim() == event)
+    }
+}
+
+/// One attempt at delivering an event to a webhook.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempt: i64,
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+    pub success: bool,
+    pub created_at: Option<String>,
+}
+
+/// Older delivery attempts are dropped past this many
+const WEBHOOK_DELIVERY_LOG_SIZE: i64 = 1000;
+
+pub async fn get_webhooks(pool: &SqlitePool) -> Result<Vec<Webhook>, sqlx::Error> {
+    sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks ORDER BY id")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_webhook(id: i64, pool: &SqlitePool) -> Result<Webhook, sqlx::Error> {
+    sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn add_webhook(
+    url: &str,
+    secret: &str,
+    events: &str,
+    pool: &SqlitePool,
+) -> Result<i64, sqlx::Error> {
+    let id = sqlx::query("INSERT INTO webhooks (url, secret, events, enabled) VALUES (?, ?, ?, 1)")
+        .bind(url)
+        .bind(secret)
+        .bind(events)
+        .execute(pool)
+        .await?
+        .last_insert_rowid();
+    Ok(id)
+}
+
+pub async fn update_webhook(
+    id: i64,
+    url: &str,
+    events: &str,
+    enabled: bool,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE webhooks SET url = ?, events = ?, enabled = ? WHERE id = ?")
+        .bind(url)
+        .bind(events)
+        .bind(enabled)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_webhook_secret(
+    id: i64,
+    secret: &str,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE webhooks SET secret = ? WHERE id = ?")
+        .bind(secret)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn remove_webhook(id: i64, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM webhooks WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// The outcome of one POST to a webhook.
+#[derive(Debug, Clone)]
+pub struct DeliveryAttempt {
+    pub attempt: u32,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+}
+
+pub async fn log_webhook_delivery(
+    webhook_id: i64,
+    event: &str,
+    payload: &str,
+    attempt: &DeliveryAttempt,
+    pool: &SqlitePool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload, attempt, status_code, error, success) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(webhook_id)
+    .bind(event)
+    .bind(payload)
+    .bind(attempt.attempt)
+    .bind(attempt.status_code)
+    .bind(&attempt.error)
+    .bind(attempt.success)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "DELETE FROM webhook_deliveries WHERE id <= (SELECT id FROM webhook_deliveries ORDER BY id DESC LIMIT 1 OFFSET ?)",
+    )
+    .bind(WEBHOOK_DELIVERY_LOG_SIZE)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Newest first, for one webhook or all of them.
+pub async fn get_webhook_deliveries(
+    webhook_id: Option<i64>,
+    limit: i64,
+    pool: &SqlitePool,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as::<_, WebhookDelivery>(
+        "SELECT * FROM webhook_deliveries WHERE ? IS NULL OR webhook_id = ? ORDER BY id DESC LIMIT ?",
+    )
+    .bind(webhook_id)
+    .bind(webhook_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
 pub fn default_encoder_settings() -> EncoderSettings {
     EncoderSettings {
         video_bitrate: 6000,
@@ -175,6 +992,16 @@ pub fn default_encoder_settings() -> EncoderSettings {
         bufsize: Some(8000),
         framerate: None,
         resolution: None,
-        use_passthrough: true
+        use_passthrough: true,
+        loudness_target: None,
+        audio_gain: None,
+        audio_limiter: false,
+        audio_channels: None,
+        audio_sample_rate: None,
+        audio_channel_map: ChannelMap::None,
+        preview_mode: PreviewMode::Hls,
+        thumbnail_interval_secs: default_thumbnail_interval(),
+        dvr_window_mins: 0,
+        dvr_max_size_mb: default_dvr_max_size(),
     }
 }
\ No newline at end of file
@@ -0,0 +1,846 @@
+use crate::{
+    access, commands,
+    config::{self, AppState, StartUpData},
+    context::{self, AppContext},
+    db::{self, EncoderSettings, RelayTargetPublic},
+    dvr,
+    events::{self, AppEvents, EventPublisher},
+    file_server, pipeline, platforms,
+    rtmp::{self, relay},
+    viewers, webhooks,
+};
+// use rtmp::stop_encoder;
+use std::sync::Arc;
+use tauri::{async_runtime, Manager};
+// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+#[tauri::command]
+fn check_if_ready(state: tauri::State<'_, Arc<config::AppState>>) -> bool {
+    state.is_ready()
+}
+
+#[tauri::command]
+fn check_if_stream_active(state: tauri::State<'_, Arc<config::AppState>>) -> bool {
+    state
+        .source_active
+        .load(std::sync::atomic::Ordering::SeqCst)
+}
+
+#[tauri::command]
+async fn get_source_stats(
+    app: tauri::State<'_, AppContext>,
+) -> Result<Option<rtmp::SourceStats>, String> {
+    commands::get_source_stats(&app).await
+}
+
+#[tauri::command]
+async fn get_source_info(
+    state: tauri::State<'_, Arc<config::AppState>>,
+) -> Result<Option<rtmp::SourceInfo>, String> {
+    Ok(state.source_info.lock().await.clone())
+}
+
+/// Closes the current publisher's session, optionally refusing its address for a while.
+#[tauri::command]
+async fn disconnect_source(
+    state: tauri::State<'_, Arc<config::AppState>>,
+    ban_minutes: Option<u64>,
+) -> Result<(), String> {
+    let connection = state
+        .source_connection
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| "No source is connected".to_string())?;
+    if let Some(minutes) = ban_minutes.filter(|m| *m > 0) {
+        println!(
+            "🚫 Banning {} for {} minutes",
+            connection.addr.ip(),
+            minutes
+        );
+        state
+            .ban_ip(
+                connection.addr.ip(),
+                std::time::Duration::from_secs(minutes * 60),
+            )
+            .await;
+    }
+    connection.cancel.cancel();
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_startup_data(
+    state: tauri::State<'_, Arc<config::AppState>>,
+) -> Result<config::StartUpData, String> {
+    let ports = state.ports.lock().await.clone();
+    Ok(StartUpData {
+        ports,
+        ips: config::get_ip_addresses().await,
+        port_fallbacks: state.port_fallbacks.lock().await.clone(),
+    })
+}
+
+#[tauri::command]
+async fn start_all_relays(
+    app: tauri::State<'_, AppContext>,
+    group_id: Option<i64>,
+) -> Result<(), String> {
+    commands::start_all_relays(&app, group_id).await
+}
+
+#[tauri::command]
+async fn stop_all_relays(
+    app: tauri::State<'_, AppContext>,
+    group_id: Option<i64>,
+) -> Result<(), String> {
+    commands::stop_all_relays(&app, group_id).await
+}
+
+#[tauri::command]
+async fn start_relay(app: tauri::State<'_, AppContext>, id: i64) -> Result<(), String> {
+    commands::start_relay(&app, id).await
+}
+
+#[tauri::command]
+async fn stop_relay(app: tauri::State<'_, AppContext>, id: i64) -> Result<(), String> {
+    commands::stop_relay(&app, id).await
+}
+
+#[tauri::command]
+async fn add_relay_target(
+    state: tauri::State<'_, Arc<AppState>>,
+    stream_key: &str,
+    url: &str,
+    tag: &str,
+) -> Result<(), String> {
+    let url = config::validate_relay_url(url)?;
+    let stream_key = config::validate_stream_key(stream_key)?;
+    let pool = db::get_db_pool();
+    db::add_relay_target(&url, &stream_key, tag, None, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.redactor.add_key(&stream_key);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_platforms() -> Vec<platforms::Platform> {
+    platforms::get_platforms().to_vec()
+}
+
+#[tauri::command]
+async fn add_platform_relay_target(
+    state: tauri::State<'_, Arc<AppState>>,
+    platform_id: &str,
+    stream_key: &str,
+    ingest_url: Option<String>,
+    tag: Option<String>,
+) -> Result<Vec<platforms::PlatformWarning>, String> {
+    let platform = platforms::find_platform(platform_id)
+        .ok_or_else(|| format!("Unknown platform '{}'", platform_id))?;
+    let url = platforms::resolve_ingest_url(platform, ingest_url.as_deref())?;
+    let url = config::validate_relay_url(&url)?;
+    let stream_key = config::validate_stream_key(stream_key)?;
+    let tag = tag.unwrap_or_else(|| platform.name.to_string());
+    let pool = db::get_db_pool();
+    db::add_relay_target(&url, &stream_key, &tag, Some(platform.id), &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.redactor.add_key(&stream_key);
+
+    let settings = state.encoder_settings.lock().await.clone();
+    let source = state.source_stats.lock().await.clone();
+    Ok(platforms::check_encoder_settings(
+        platform,
+        &settings,
+        source.as_ref(),
+    ))
+}
+
+#[tauri::command]
+async fn get_platform_warnings(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<platforms::PlatformWarning>, String> {
+    let pool = db::get_db_pool();
+    let targets = db::get_active_relay_targets(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let settings = state.encoder_settings.lock().await.clone();
+    let source = state.source_stats.lock().await.clone();
+    Ok(platforms::check_relay_targets(
+        &targets,
+        &settings,
+        source.as_ref(),
+    ))
+}
+
+#[tauri::command]
+async fn update_relay_target(
+    app: tauri::State<'_, AppContext>,
+    state: tauri::State<'_, Arc<AppState>>,
+    id: i64,
+    url: Option<String>,
+    stream_key: Option<String>,
+    tag: Option<String>,
+) -> Result<RelayTargetPublic, String> {
+    let url = url.as_deref().map(config::validate_relay_url).transpose()?;
+    let stream_key = stream_key
+        .as_deref()
+        .map(config::validate_stream_key)
+        .transpose()?;
+    let pool = db::get_db_pool();
+    let target = db::update_relay_target(
+        id,
+        url.as_deref(),
+        stream_key.as_deref(),
+        tag.as_deref(),
+        &pool,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    state.redactor.add_key(&target.stream_key);
+    relay::restart_relay_if_active(&app, &target).await;
+    Ok(RelayTargetPublic::from_relay_target(&target))
+}
+
+#[tauri::command]
+async fn get_relay_targets() -> Result<Vec<db::RelayTargetPublic>, String> {
+    commands::get_relay_targets().await
+}
+
+#[tauri::command]
+async fn toggle_relay_target(id: i64, active: bool) -> Result<(), String> {
+    let pool = db::get_db_pool();
+    db::toggle_relay_target(id, active, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_relay_target(id: i64) -> Result<(), String> {
+    let pool = db::get_db_pool();
+    db::remove_relay_target(id, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Changes the audio a target gets. ffmpeg can't swap its audio graph on the fly,
+/// so a running relay reconnects to the platform and viewers see a short drop.
+#[tauri::command]
+async fn set_relay_audio_policy(
+    app: tauri::State<'_, AppContext>,
+    id: i64,
+    policy: db::AudioPolicy,
+    audio_file: Option<String>,
+) -> Result<(), String> {
+    let audio_file = match policy {
+        db::AudioPolicy::Replace => audio_file.filter(|f| !f.trim().is_empty()),
+        _ => None,
+    };
+    if let Some(file) = &audio_file {
+        if !std::path::Path::new(file).is_file() {
+            return Err(format!("Audio file '{}' does not exist", file));
+        }
+    }
+    let pool = db::get_db_pool();
+    let target = db::set_relay_audio_policy(id, policy, audio_file.as_deref(), &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    relay::restart_relay_if_active(&app, &target).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_relay_transform(
+    app: tauri::State<'_, AppContext>,
+    id: i64,
+    transform: db::OutputTransform,
+) -> Result<(), String> {
+    if !transform.is_identity() {
+        rtmp::transform_filter(&transform)?;
+    }
+    let pool = db::get_db_pool();
+    let target = db::set_relay_transform(id, &transform, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    relay::restart_relay_if_active(&app, &target).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_relay_groups() -> Result<Vec<db::RelayGroup>, String> {
+    let pool = db::get_db_pool();
+    db::get_relay_groups(&pool).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_relay_group(name: &str, target_ids: Vec<i64>) -> Result<i64, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Group name cannot be empty".into());
+    }
+    let pool = db::get_db_pool();
+    db::add_relay_group(name, &target_ids, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_relay_group(
+    id: i64,
+    name: Option<String>,
+    target_ids: Option<Vec<i64>>,
+) -> Result<(), String> {
+    let name = name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err("Group name cannot be empty".into());
+    }
+    let pool = db::get_db_pool();
+    db::update_relay_group(id, name, target_ids.as_deref(), &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_relay_group(id: i64) -> Result<(), String> {
+    let pool = db::get_db_pool();
+    db::remove_relay_group(id, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn activate_relay_group(id: i64) -> Result<(), String> {
+    let pool = db::get_db_pool();
+    db::activate_relay_group(id, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_encoder_settings(
+    app: tauri::State<'_, AppContext>,
+) -> Result<EncoderSettings, String> {
+    commands::get_encoder_settings(&app).await
+}
+
+#[tauri::command]
+async fn update_encoder_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+    settings: EncoderSettings,
+) -> Result<Vec<platforms::PlatformWarning>, String> {
+    rtmp::validate_encoder_settings(&settings)?;
+    let pool = db::get_db_pool();
+    db::save_encoder_settings(&settings, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let targets = db::get_active_relay_targets(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let source = state.source_stats.lock().await.clone();
+    let warnings = platforms::check_relay_targets(&targets, &settings, source.as_ref());
+    *state.encoder_settings.lock().await = settings;
+    Ok(warnings)
+}
+
+#[tauri::command]
+async fn get_overlay_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<db::OverlaySettings, String> {
+    Ok(state.overlay_settings.lock().await.clone())
+}
+
+#[tauri::command]
+async fn update_overlay_settings(
+    app: tauri::State<'_, AppContext>,
+    state: tauri::State<'_, Arc<AppState>>,
+    settings: db::OverlaySettings,
+) -> Result<(), String> {
+    rtmp::validate_overlay_settings(&settings)?;
+    let pool = db::get_db_pool();
+    db::save_overlay_settings(&settings, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    config::write_overlay_text(&app, &settings.text_content).map_err(|e| e.to_string())?;
+    *state.overlay_settings.lock().await = settings;
+    Ok(())
+}
+
+/// Updates the text overlay; a running encoder picks it up on the next frame.
+#[tauri::command]
+async fn set_overlay_text(
+    app: tauri::State<'_, AppContext>,
+    state: tauri::State<'_, Arc<AppState>>,
+    text: String,
+) -> Result<(), String> {
+    let mut settings = state.overlay_settings.lock().await;
+    config::write_overlay_text(&app, &text).map_err(|e| e.to_string())?;
+    settings.text_content = text;
+    let pool = db::get_db_pool();
+    db::save_overlay_settings(&settings, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_ingest_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<db::IngestSettings, String> {
+    Ok(state.ingest_settings.lock().await.clone())
+}
+
+/// New timeouts apply from the next ingest connection; a new bind address
+/// rebinds the RTMP server straight away.
+#[tauri::command]
+async fn update_ingest_settings(
+    app: tauri::State<'_, AppContext>,
+    state: tauri::State<'_, Arc<AppState>>,
+    settings: db::IngestSettings,
+) -> Result<(), String> {
+    let ip = config::ingest_bind_ip(&settings)?;
+    let current_ip = state
+        .rtmp_listener
+        .lock()
+        .await
+        .as_ref()
+        .map(|listener| listener.addr.ip());
+    let port = state.ports.lock().await.rtmp_port;
+    if current_ip != Some(ip) {
+        rtmp::start_rtmp_listener(&app, ip, port)
+            .await
+            .map_err(|e| format!("Failed to listen on {}:{}: {}", ip, port, e))?;
+    }
+    let pool = db::get_db_pool();
+    if let Err(e) = db::save_ingest_settings(&settings, &pool).await {
+        // keep listening where the saved settings say
+        if let Some(previous_ip) = current_ip.filter(|previous_ip| *previous_ip != ip) {
+            if let Err(e) = rtmp::start_rtmp_listener(&app, previous_ip, port).await {
+                eprintln!(
+                    "⚠️ Failed to move RTMP server back to {}: {}",
+                    previous_ip, e
+                );
+            }
+        }
+        return Err(e.to_string());
+    }
+    *state.ingest_settings.lock().await = settings;
+    Ok(())
+}
+
+/// Moves the RTMP and file servers to new ports without restarting the app.
+#[tauri::command]
+async fn update_ports(
+    app: tauri::State<'_, AppContext>,
+    state: tauri::State<'_, Arc<AppState>>,
+    rtmp_port: u16,
+    file_port: u16,
+) -> Result<config::PortInfo, String> {
+    if rtmp_port == 0 || file_port == 0 {
+        return Err("Ports must be between 1 and 65535".into());
+    }
+    if rtmp_port == file_port {
+        return Err("The RTMP and file server ports must be different".into());
+    }
+    let current = state.ports.lock().await.clone();
+    for (port, current_port) in [
+        (rtmp_port, current.rtmp_port),
+        (file_port, current.file_port),
+    ] {
+        if port != current_port && !config::is_port_available(port).await {
+            return Err(format!("Port {} is already in use", port));
+        }
+    }
+    let pool = db::get_db_pool();
+    let mut ports = current.clone();
+    if rtmp_port != current.rtmp_port {
+        let ip = config::ingest_bind_ip(&*state.ingest_settings.lock().await)?;
+        rtmp::start_rtmp_listener(&app, ip, rtmp_port)
+            .await
+            .map_err(|e| format!("Failed to listen on port {}: {}", rtmp_port, e))?;
+        ports.rtmp_port = rtmp_port;
+    }
+    // keep the stored ports in sync with what is bound even if the file server fails
+    let result = if file_port != current.file_port {
+        file_server::bind_file_server(&app, file_port).await
+    } else {
+        Ok(())
+    };
+    if result.is_ok() {
+        ports.file_port = file_port;
+    }
+    config::save_ports(&ports, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    *state.ports.lock().await = ports.clone();
+    app.publish(AppEvents::PortsChanged, &ports);
+    result.map(|_| ports)
+}
+
+#[tauri::command]
+async fn get_ingest_status(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<config::IngestStatus, String> {
+    let listen_address = state
+        .rtmp_listener
+        .lock()
+        .await
+        .as_ref()
+        .map(|listener| listener.addr.to_string());
+    Ok(config::IngestStatus {
+        listen_address,
+        rejected_connections: state
+            .rejected_connections
+            .load(std::sync::atomic::Ordering::SeqCst),
+    })
+}
+
+#[tauri::command]
+async fn get_access_rules() -> Result<Vec<db::AccessRule>, String> {
+    let pool = db::get_db_pool();
+    db::get_access_rules(&pool).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_access_rule(
+    state: tauri::State<'_, Arc<AppState>>,
+    cidr: String,
+    action: db::AccessAction,
+) -> Result<i64, String> {
+    access::Cidr::parse(&cidr)?;
+    let pool = db::get_db_pool();
+    let id = db::add_access_rule(cidr.trim(), action, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    state
+        .reload_access_rules(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+#[tauri::command]
+async fn remove_access_rule(state: tauri::State<'_, Arc<AppState>>, id: i64) -> Result<(), String> {
+    let pool = db::get_db_pool();
+    db::remove_access_rule(id, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    state
+        .reload_access_rules(&pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_api_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<db::ApiSettings, String> {
+    Ok(state.api_settings.lock().await.clone())
+}
+
+/// Turns the HTTP control API on the file server on or off.
+#[tauri::command]
+async fn set_api_enabled(
+    state: tauri::State<'_, Arc<AppState>>,
+    enabled: bool,
+) -> Result<(), String> {
+    let mut settings = state.api_settings.lock().await;
+    let mut updated = settings.clone();
+    updated.enabled = enabled;
+    let pool = db::get_db_pool();
+    db::save_api_settings(&updated, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    *settings = updated;
+    Ok(())
+}
+
+#[tauri::command]
+async fn rotate_api_key(state: tauri::State<'_, Arc<AppState>>) -> Result<String, String> {
+    let mut settings = state.api_settings.lock().await;
+    let mut rotated = settings.clone();
+    rotated.api_key = db::generate_access_token();
+    let pool = db::get_db_pool();
+    db::save_api_settings(&rotated, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    *settings = rotated;
+    Ok(settings.api_key.clone())
+}
+
+#[tauri::command]
+async fn get_webhooks() -> Result<Vec<db::Webhook>, String> {
+    db::get_webhooks(db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Adds a webhook with a fresh signing secret and returns its id.
+#[tauri::command]
+async fn add_webhook(url: String, events: Vec<String>) -> Result<i64, String> {
+    let url = url.trim();
+    let events = webhooks::validate(url, &events)?;
+    db::add_webhook(
+        url,
+        &webhooks::generate_secret(),
+        &events,
+        db::get_db_pool(),
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_webhook(
+    id: i64,
+    url: String,
+    events: Vec<String>,
+    enabled: bool,
+) -> Result<(), String> {
+    let url = url.trim();
+    let events = webhooks::validate(url, &events)?;
+    db::update_webhook(id, url, &events, enabled, db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_webhook(id: i64) -> Result<(), String> {
+    db::remove_webhook(id, db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rotate_webhook_secret(id: i64) -> Result<String, String> {
+    let secret = webhooks::generate_secret();
+    db::set_webhook_secret(id, &secret, db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(secret)
+}
+
+/// Delivery attempts, newest first, for one webhook or all of them.
+#[tauri::command]
+async fn get_webhook_deliveries(
+    webhook_id: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Vec<db::WebhookDelivery>, String> {
+    db::get_webhook_deliveries(webhook_id, limit.unwrap_or(100), db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Sends a test event to the webhook and returns the receiver's status code.
+#[tauri::command]
+async fn test_webhook(id: i64) -> Result<u16, String> {
+    let webhook = db::get_webhook(id, db::get_db_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    let result = webhooks::send_test(&webhook).await;
+    match (result.success, result.status_code) {
+        (true, Some(status_code)) => Ok(status_code),
+        _ => Err(result.error.unwrap_or_else(|| "Delivery failed".into())),
+    }
+}
+
+#[tauri::command]
+async fn get_dvr_status(state: tauri::State<'_, Arc<AppState>>) -> Result<dvr::DvrStatus, String> {
+    Ok(state.dvr.status().await)
+}
+
+/// Saves a full-resolution still of the live program and returns its path.
+#[tauri::command]
+async fn capture_snapshot(
+    app: tauri::State<'_, AppContext>,
+    directory: Option<String>,
+) -> Result<String, String> {
+    let dir = directory
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| config::snapshot_dir(&app));
+    let path = rtmp::capture_snapshot(&app, &dir)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+#[tauri::command]
+async fn get_preview_access(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<db::PreviewAccessSettings, String> {
+    Ok(state.preview_access.lock().await.clone())
+}
+
+/// Rebinds the file server on all interfaces, or back to loopback only.
+#[tauri::command]
+async fn set_preview_lan_access(
+    app: tauri::State<'_, AppContext>,
+    state: tauri::State<'_, Arc<AppState>>,
+    lan_access: bool,
+) -> Result<(), String> {
+    let mut settings = state.preview_access.lock().await.clone();
+    if settings.lan_access == lan_access {
+        return Ok(());
+    }
+    settings.lan_access = lan_access;
+    let previous = std::mem::replace(&mut *state.preview_access.lock().await, settings.clone());
+    let port = state.ports.lock().await.file_port;
+    if let Err(e) = file_server::bind_file_server(&app, port).await {
+        *state.preview_access.lock().await = previous;
+        return Err(e);
+    }
+    let pool = db::get_db_pool();
+    db::save_preview_access_settings(&settings, &pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Replaces the access token. LAN clients need the new one for their next request.
+#[tauri::command]
+async fn rotate_preview_token(state: tauri::State<'_, Arc<AppState>>) -> Result<String, String> {
+    let mut settings = state.preview_access.lock().await;
+    let mut rotated = settings.clone();
+    rotated.access_token = db::generate_access_token();
+    let pool = db::get_db_pool();
+    db::save_preview_access_settings(&rotated, &pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    *settings = rotated;
+    Ok(settings.access_token.clone())
+}
+
+#[tauri::command]
+async fn get_preview_viewers(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<viewers::ViewerInfo>, String> {
+    Ok(state.viewers.active().await)
+}
+
+// async fn cleanup_all(app: &AppHandle) {
+//     // Stop all relays
+//     let _ = stop_all_relays(app.clone()).await;
+//     // Stop encoder if active
+//     stop_encoder(app).await;
+//     // delete hls_files
+//     config::hls_output_dir()
+//         .read_dir()
+//         .expect("Failed to read directory")
+//         .filter_map(|entry| entry.ok())
+//         .for_each(|entry| {
+//             let path = entry.path();
+//             if path.is_file() {
+//                 std::fs::remove_file(path).expect("Failed to delete file");
+//             }
+//         });
+
+//     println!("✅ Cleanup complete. Safe to exit.");
+// }
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_sql::Builder::new().build())
+        .plugin(tauri_plugin_shell::init())
+        .invoke_handler(tauri::generate_handler![
+            check_if_ready,
+            get_startup_data,
+            check_if_stream_active,
+            get_source_stats,
+            get_source_info,
+            disconnect_source,
+            add_relay_target,
+            update_relay_target,
+            get_platforms,
+            add_platform_relay_target,
+            get_platform_warnings,
+            get_relay_targets,
+            toggle_relay_target,
+            remove_relay_target,
+            set_relay_audio_policy,
+            set_relay_transform,
+            get_relay_groups,
+            add_relay_group,
+            update_relay_group,
+            remove_relay_group,
+            activate_relay_group,
+            start_all_relays,
+            stop_all_relays,
+            stop_relay,
+            start_relay,
+            get_encoder_settings,
+            update_encoder_settings,
+            get_overlay_settings,
+            update_overlay_settings,
+            set_overlay_text,
+            get_ingest_settings,
+            update_ingest_settings,
+            get_ingest_status,
+            update_ports,
+            get_access_rules,
+            add_access_rule,
+            remove_access_rule,
+            get_preview_access,
+            set_preview_lan_access,
+            rotate_preview_token,
+            get_preview_viewers,
+            capture_snapshot,
+            get_dvr_status,
+            get_api_settings,
+            set_api_enabled,
+            rotate_api_key,
+            get_webhooks,
+            add_webhook,
+            update_webhook,
+            remove_webhook,
+            rotate_webhook_secret,
+            get_webhook_deliveries,
+            test_webhook,
+        ])
+        .setup(|app| {
+            let app_handle = app.handle();
+            let app_state = Arc::new(config::AppState::new(0, 0));
+            // Create the log directory if it doesn't exist
+            // let data_dir = app
+            //     .path_resolver()
+            //     .app_data_dir()
+            //     .unwrap_or_else(|| std::env::current_dir().unwrap());
+
+            let paths = context::AppPaths::resolve(app_handle);
+            let context = AppContext::new(app_state.clone(), paths);
+            app.manage(app_state);
+            app.manage(context.clone());
+            events::forward_to_webview(app_handle.clone());
+            pipeline::prepare_dirs(&context);
+            async_runtime::spawn(async move {
+                let _ = db::init_db(&config::get_data_dir(&context))
+                    .await
+                    .expect("❌ Failed to init DB");
+                println!("✅ Database ready");
+                // the webhook dispatcher reads the DB, and the services spawn
+                // tokio tasks, so they start here rather than in setup
+                pipeline::start_services(&context);
+                let port_info = pipeline::load_settings(&context).await;
+                pipeline::serve(&context, &port_info);
+
+                // tokio::spawn(wait_for_ready(app_handle_clone, app_state));
+            });
+
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+// async fn wait_for_ready(app: AppHandle, state: &Arc<config::AppState>) {
+//     use tokio::time::{sleep, Duration};
+
+//     // Polling until ready
+//     loop {
+//         if state.is_ready() {
+//             println!("✅ Both RTMP and file servers are ready.");
+//             let _ = app.emit("servers-ready", &state.ports);
+//             break;
+//         }
+//         sleep(Duration::from_millis(100)).await;
+//     }
+// }
@@ -0,0 +1,188 @@
+use crate::llhls::parse_media_playlist;
+use serde::Serialize;
+use std::{collections::VecDeque, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+pub const DVR_PLAYLIST: &str = "dvr.m3u8";
+const LIVE_PLAYLIST: &str = "playlist.m3u8";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    uri: String,
+    duration: f64,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DvrStatus {
+    pub active: bool,
+    /// Path of the DVR playlist on the file server
+    pub playlist: String,
+    /// How far back viewers can currently seek
+    pub duration_secs: f64,
+    pub size_bytes: u64,
+    pub window_secs: u64,
+    pub max_size_bytes: u64,
+}
+
+#[derive(Debug)]
+struct Window {
+    dir: PathBuf,
+    segments: VecDeque<Segment>,
+    next_seq: u64,
+    window_secs: f64,
+    max_bytes: u64,
+}
+
+impl Window {
+    fn duration(&self) -> f64 {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+
+    fn size(&self) -> u64 {
+        self.segments.iter().map(|s| s.size).sum()
+    }
+
+    /// Drops the oldest segments past the window or size cap. Segments still in
+    /// ffmpeg's live playlist are kept, the live preview needs them.
+    async fn trim(&mut self, live_first_seq: u64) {
+        let mut duration = self.duration();
+        let mut size = self.size();
+        while duration > self.window_secs || size > self.max_bytes {
+            let Some(oldest) = self.segments.front() else {
+                break;
+            };
+            if oldest.seq >= live_first_seq {
+                break;
+            }
+            let oldest = self.segments.pop_front().unwrap();
+            duration -= oldest.duration;
+            size -= oldest.size;
+            let _ = tokio::fs::remove_file(self.dir.join(&oldest.uri)).await;
+        }
+    }
+
+    fn render(&self) -> Option<String> {
+        let first = self.segments.front()?;
+        let target = self
+            .segments
+            .iter()
+            .map(|s| s.duration)
+            .fold(0.0, f64::max)
+            .ceil() as u32;
+        let mut lines = vec![
+            "#EXTM3U".to_string(),
+            "#EXT-X-VERSION:3".to_string(),
+            format!("#EXT-X-TARGETDURATION:{}", target),
+            format!("#EXT-X-MEDIA-SEQUENCE:{}", first.seq),
+        ];
+        for segment in &self.segments {
+            lines.push(format!("#EXTINF:{:.6},", segment.duration));
+            lines.push(segment.uri.clone());
+        }
+        lines.push(String::new());
+        Some(lines.join("\n"))
+    }
+}
+
+/// Keeps the HLS preview segments ffmpeg writes for a rewind window and lists
+/// them in a separate sliding playlist. ffmpeg's own playlist stays short for
+/// live viewing, and segments are deleted here instead of by ffmpeg.
+#[derive(Debug, Default)]
+pub struct Dvr {
+    window: Mutex<Option<Window>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Dvr {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts following ffmpeg's HLS playlist in `dir`.
+    pub async fn start(self: &Arc<Self>, dir: PathBuf, window_mins: u32, max_size_mb: u32) {
+        *self.window.lock().await = Some(Window {
+            dir,
+            segments: VecDeque::new(),
+            next_seq: 0,
+            window_secs: window_mins as f64 * 60.0,
+            max_bytes: max_size_mb as u64 * 1024 * 1024,
+        });
+        let this = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                this.refresh().await;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+        if let Some(previous) = self.task.lock().await.replace(task) {
+            previous.abort();
+        }
+    }
+
+    /// Segments on disk are removed with the rest of the preview directory.
+    pub async fn stop(&self) {
+        if let Some(task) = self.task.lock().await.take() {
+            task.abort();
+        }
+        *self.window.lock().await = None;
+    }
+
+    async fn refresh(&self) {
+        let mut guard = self.window.lock().await;
+        let Some(window) = guard.as_mut() else {
+            return;
+        };
+        let Ok(text) = tokio::fs::read_to_string(window.dir.join(LIVE_PLAYLIST)).await else {
+            return;
+        };
+        let (first_seq, entries) = parse_media_playlist(&text);
+        let mut changed = false;
+        for (index, (duration, uri)) in entries.into_iter().enumerate() {
+            let seq = first_seq + index as u64;
+            if seq < window.next_seq {
+                continue;
+            }
+            let size = tokio::fs::metadata(window.dir.join(&uri))
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            window.segments.push_back(Segment {
+                seq,
+                uri,
+                duration,
+                size,
+            });
+            window.next_seq = seq + 1;
+            changed = true;
+        }
+        if !changed {
+            return;
+        }
+        window.trim(first_seq).await;
+        let Some(playlist) = window.render() else {
+            return;
+        };
+        // write then rename so players never read a half-written playlist
+        let tmp_path = window.dir.join(format!("{}.tmp", DVR_PLAYLIST));
+        if tokio::fs::write(&tmp_path, playlist).await.is_ok() {
+            if let Err(e) = tokio::fs::rename(&tmp_path, window.dir.join(DVR_PLAYLIST)).await {
+                eprintln!("⚠️ Failed to update DVR playlist: {}", e);
+            }
+        }
+    }
+
+    pub async fn status(&self) -> DvrStatus {
+        let guard = self.window.lock().await;
+        DvrStatus {
+            active: guard.is_some(),
+            playlist: format!("/{}", DVR_PLAYLIST),
+            duration_secs: guard.as_ref().map(Window::duration).unwrap_or(0.0),
+            size_bytes: guard.as_ref().map(Window::size).unwrap_or(0),
+            window_secs: guard.as_ref().map(|w| w.window_secs as u64).unwrap_or(0),
+            max_size_bytes: guard.as_ref().map(|w| w.max_bytes).unwrap_or(0),
+        }
+    }
+}
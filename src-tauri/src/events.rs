@@ -1,3 +1,19 @@
+#[cfg(feature = "desktop")]
+use crate::config::AppState;
+use serde::Serialize;
+#[cfg(feature = "desktop")]
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "desktop")]
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events waiting for slow subscribers before they start missing some
+const BUS_CAPACITY: usize = 1024;
+/// Same for the high-rate telemetry, which has its own channel so a burst of
+/// it can't push lifecycle events out of a slow subscriber's backlog
+const TELEMETRY_CAPACITY: usize = 256;
+
 #[derive(Debug, Clone)]
 pub enum AppEvents {
     StreamPreviewActive,
@@ -6,9 +22,22 @@ pub enum AppEvents {
     // ServersReady,
     StreamEnded,
     StreamPreviewEnded,
-    RelayActive, 
+    RelayActive,
     RelayEnded,
     RelayFailed,
+    RelayRecovered,
+    EncoderCrashed,
+    AudioLevels,
+    AudioSilenceDetected,
+    AudioSilenceEnded,
+    SourceStats,
+    SourceWarning,
+    SourceInfoChanged,
+    IngestTimeout,
+    IngestRejected,
+    PortsChanged,
+    PortFallback,
+    ThumbnailUpdated,
 }
 
 impl AppEvents {
@@ -23,6 +52,150 @@ impl AppEvents {
             AppEvents::RelayActive => "relay-active",
             AppEvents::RelayEnded => "relay-ended",
             AppEvents::RelayFailed => "relay-failed",
+            AppEvents::RelayRecovered => "relay-recovered",
+            AppEvents::EncoderCrashed => "encoder-crashed",
+            AppEvents::AudioLevels => "audio-levels",
+            AppEvents::AudioSilenceDetected => "audio-silence-detected",
+            AppEvents::AudioSilenceEnded => "audio-silence-ended",
+            AppEvents::SourceStats => "source-stats",
+            AppEvents::SourceWarning => "source-warning",
+            AppEvents::SourceInfoChanged => "source-info-changed",
+            AppEvents::IngestTimeout => "ingest-timeout",
+            AppEvents::IngestRejected => "ingest-rejected",
+            AppEvents::PortsChanged => "ports-changed",
+            AppEvents::PortFallback => "port-fallback",
+            AppEvents::ThumbnailUpdated => "thumbnail-updated",
         }
     }
+
+    /// Events published several times a second, which are fine to miss.
+    fn is_telemetry(&self) -> bool {
+        matches!(self, AppEvents::AudioLevels | AppEvents::SourceStats)
+    }
+}
+
+/// One published event, as delivered to every subscriber.
+#[derive(Debug, Clone, Serialize)]
+pub struct BusEvent {
+    pub event: &'static str,
+    pub payload: serde_json::Value,
+    /// Unix milliseconds
+    pub timestamp: u64,
+}
+
+/// Fans every AppEvent out to the webview and to external listeners such as
+/// the WebSocket event stream.
+#[derive(Debug)]
+pub struct EventBus {
+    tx: broadcast::Sender<BusEvent>,
+    telemetry: broadcast::Sender<BusEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            tx: broadcast::channel(BUS_CAPACITY).0,
+            telemetry: broadcast::channel(TELEMETRY_CAPACITY).0,
+        }
+    }
+
+    pub fn publish<S: Serialize>(&self, event: AppEvents, payload: S) {
+        let payload = match serde_json::to_value(payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("⚠️ Failed to serialize {} event: {}", event.as_str(), e);
+                return;
+            }
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let tx = if event.is_telemetry() {
+            &self.telemetry
+        } else {
+            &self.tx
+        };
+        // no subscribers is fine, nothing is listening yet
+        let _ = tx.send(BusEvent {
+            event: event.as_str(),
+            payload,
+            timestamp,
+        });
+    }
+
+    pub fn subscribe(&self) -> EventReceiver {
+        EventReceiver {
+            events: self.tx.subscribe(),
+            telemetry: self.telemetry.subscribe(),
+        }
+    }
+}
+
+/// Receives from both bus channels. Lagging only ever loses events from the
+/// channel that overflowed.
+pub struct EventReceiver {
+    events: broadcast::Receiver<BusEvent>,
+    telemetry: broadcast::Receiver<BusEvent>,
+}
+
+impl EventReceiver {
+    pub async fn recv(&mut self) -> Result<BusEvent, RecvError> {
+        tokio::select! {
+            biased;
+            event = self.events.recv() => event,
+            event = self.telemetry.recv() => event,
+        }
+    }
+}
+
+pub trait EventPublisher {
+    fn publish<S: Serialize>(&self, event: AppEvents, payload: S);
+}
+
+/// Delivers bus events to the webview under the same names and payloads as before.
+#[cfg(feature = "desktop")]
+pub fn forward_to_webview(app: AppHandle) {
+    let mut rx = app.state::<Arc<AppState>>().events.subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Err(e) = app.emit(event.event, &event.payload) {
+                        eprintln!("⚠️ Failed to emit {} event: {}", event.event, e);
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("⚠️ Webview missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn telemetry_bursts_do_not_push_out_lifecycle_events() {
+        let bus = EventBus::new();
+        let mut rx = bus.subscribe();
+        bus.publish(AppEvents::StreamEnded, "key");
+        for level in 0..BUS_CAPACITY * 2 {
+            bus.publish(AppEvents::AudioLevels, level);
+        }
+
+        let ended = rx.recv().await.unwrap();
+        assert_eq!(ended.event, "stream-ended");
+        assert!(matches!(rx.recv().await, Err(RecvError::Lagged(_))));
+        assert_eq!(rx.recv().await.unwrap().event, "audio-levels");
+    }
 }
@@ -1,23 +1,402 @@
-use crate::config;
-use std::sync::{atomic::Ordering, Arc};
-use tauri::{AppHandle, Manager};
-use warp::Filter;
-
-pub async fn start_file_server(app: AppHandle, port: u16) {
-    let ouptut_dir: std::path::PathBuf = config::hls_output_dir(&app);
-    std::fs::create_dir_all(&ouptut_dir).expect("Failed to create output dir");
+use crate::{
+    api,
+    config::{self, ListenerHandle},
+    context::AppContext,
+    llhls::{LlHls, PlaylistError},
+    metrics, pipeline,
+    rtmp::{self, PreviewViewer},
+};
+use futures_util::SinkExt;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+};
+use warp::{
+    http::{
+        header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, SET_COOKIE},
+        StatusCode,
+    },
+    hyper::{body::Bytes, Body},
+    path::FullPath,
+    reject::Reject,
+    reply::{with_header, with_status, Response},
+    ws::{Message, WebSocket, Ws},
+    Filter, Rejection, Reply,
+};
+
+const TOKEN_QUERY: &str = "token";
+const TOKEN_HEADER: &str = "x-access-token";
+/// Set once a LAN client authenticates with the query parameter, so the
+/// playlist's relative segment URLs work without the token
+const TOKEN_COOKIE: &str = "preview_token";
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+fn cookie_token(cookies: &str) -> Option<&str> {
+    cookies.split(';').find_map(|cookie| {
+        let (name, value) = cookie.trim().split_once('=')?;
+        (name == TOKEN_COOKIE).then_some(value)
+    })
+}
+
+/// Records every request as a viewer and, for clients other than this machine,
+/// requires the access token. Extracts the token to hand back as a cookie.
+fn authorize(
+    app: AppContext,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::path::full())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>(TOKEN_HEADER))
+        .and(warp::header::optional::<String>("cookie"))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and_then(
+            move |remote: Option<SocketAddr>,
+                  path: FullPath,
+                  query: HashMap<String, String>,
+                  header: Option<String>,
+                  cookies: Option<String>,
+                  user_agent: Option<String>| {
+                let app = app.clone();
+                async move {
+                    let ip = remote_ip(remote);
+                    let state = app.state();
+                    state.viewers.record(ip, path.as_str(), user_agent).await;
+                    if ip.is_loopback() {
+                        return Ok(None);
+                    }
+                    let token = state.preview_access.lock().await.access_token.clone();
+                    if header.as_deref() == Some(token.as_str())
+                        || cookies.as_deref().and_then(cookie_token) == Some(token.as_str())
+                    {
+                        return Ok(None);
+                    }
+                    if query.get(TOKEN_QUERY) == Some(&token) {
+                        return Ok(Some(token));
+                    }
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            },
+        )
+}
+
+fn set_token_cookie(token: Option<String>, reply: impl Reply) -> Response {
+    let mut response = reply.into_response();
+    let cookie = token.and_then(|token| {
+        HeaderValue::from_str(&format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict",
+            TOKEN_COOKIE, token
+        ))
+        .ok()
+    });
+    if let Some(cookie) = cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    response
+}
+
+async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        return Ok(with_status(
+            "Access token required",
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+    Err(rejection)
+}
+
+fn remote_ip(remote: Option<SocketAddr>) -> IpAddr {
+    remote
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// LL-HLS playlist and media, only answered while the encoder runs in LL-HLS
+/// mode so plain HLS falls through to the preview directory.
+fn ll_hls_routes(
+    ll_hls: Arc<LlHls>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let playlist_ll_hls = ll_hls.clone();
+    let playlist = warp::path!("playlist.m3u8")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |query: HashMap<String, String>| {
+            let ll_hls = playlist_ll_hls.clone();
+            async move {
+                if !ll_hls.is_active().await {
+                    return Err(warp::reject::not_found());
+                }
+                // _HLS_msn/_HLS_part turn this into a blocking reload
+                let msn = query.get("_HLS_msn").and_then(|v| v.parse().ok());
+                let part = query.get("_HLS_part").and_then(|v| v.parse().ok());
+                match ll_hls.playlist(msn, part).await {
+                    Ok(body) => Ok(with_header(
+                        with_header(body, "Content-Type", "application/vnd.apple.mpegurl"),
+                        "Cache-Control",
+                        "no-cache",
+                    )
+                    .into_response()),
+                    Err(PlaylistError::TimedOut) => Ok(with_status(
+                        "The requested part is not available yet",
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                    .into_response()),
+                    Err(PlaylistError::Unavailable) => Err(warp::reject::not_found()),
+                }
+            }
+        });
+    let media = warp::path!("ll" / String / String)
+        .and(warp::get())
+        .and_then(move |session: String, name: String| {
+            let ll_hls = ll_hls.clone();
+            async move {
+                let data = ll_hls
+                    .file(&session, &name)
+                    .await
+                    .ok_or_else(warp::reject::not_found)?;
+                // names repeat across encoder runs, but never within a session
+                Ok::<_, warp::Rejection>(with_header(
+                    with_header(data, "Content-Type", "video/mp4"),
+                    "Cache-Control",
+                    "max-age=60, immutable",
+                ))
+            }
+        });
+    playlist.or(media)
+}
+
+/// HTTP-FLV and WS-FLV preview of the program feed for flv.js/mpegts.js, only
+/// answered while the encoder runs.
+fn flv_preview_routes(
+    app: AppContext,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let http_app = app.clone();
+    let http = warp::path!("live.flv")
+        .and(warp::get())
+        .and(warp::addr::remote())
+        .and_then(move |remote: Option<SocketAddr>| {
+            let app = http_app.clone();
+            async move {
+                let (start, mut viewer) = rtmp::subscribe_preview(&app)
+                    .await
+                    .ok_or_else(warp::reject::not_found)?;
+                let (mut sender, body) = Body::channel();
+                let ip = remote_ip(remote);
+                app.state().viewers.stream_opened(ip).await;
+                tokio::spawn(async move {
+                    if sender.send_data(Bytes::from(start)).await.is_ok() {
+                        while let Some(tag) = viewer.next().await {
+                            if sender
+                                .send_data(Bytes::copy_from_slice(&tag))
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                    app.state().viewers.stream_closed(ip).await;
+                });
+                let mut response = Response::new(body);
+                let headers = response.headers_mut();
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("video/x-flv"));
+                headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                Ok::<_, warp::Rejection>(response)
+            }
+        });
+    let ws = warp::path!("ws" / "live")
+        .and(warp::ws())
+        .and(warp::addr::remote())
+        .and_then(move |ws: Ws, remote: Option<SocketAddr>| {
+            let app = app.clone();
+            async move {
+                let (start, viewer) = rtmp::subscribe_preview(&app)
+                    .await
+                    .ok_or_else(warp::reject::not_found)?;
+                Ok::<_, warp::Rejection>(ws.on_upgrade(move |socket| async move {
+                    let ip = remote_ip(remote);
+                    let state = app.state();
+                    state.viewers.stream_opened(ip).await;
+                    stream_flv_to_websocket(socket, start, viewer).await;
+                    state.viewers.stream_closed(ip).await;
+                }))
+            }
+        });
+    http.or(ws)
+}
+
+async fn stream_flv_to_websocket(mut socket: WebSocket, start: Vec<u8>, mut viewer: PreviewViewer) {
+    if socket.send(Message::binary(start)).await.is_err() {
+        return;
+    }
+    while let Some(tag) = viewer.next().await {
+        if socket.send(Message::binary(tag.to_vec())).await.is_err() {
+            return;
+        }
+    }
+    let _ = socket.close().await;
+}
+
+/// The latest live thumbnail, refreshed by the encoder every few seconds.
+fn snapshot_route(
+    app: AppContext,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("snapshot.jpg")
+        .and(warp::get())
+        .and_then(move || {
+            let app = app.clone();
+            async move {
+                let thumbnail = app
+                    .state()
+                    .thumbnail
+                    .lock()
+                    .await
+                    .clone()
+                    .ok_or_else(warp::reject::not_found)?;
+                let mut response = Response::new(Body::from(thumbnail.jpeg));
+                let headers = response.headers_mut();
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/jpeg"));
+                headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                Ok::<_, warp::Rejection>(response)
+            }
+        })
+}
+
+/// Prometheus scrape endpoint, behind the same access token as the preview.
+fn metrics_route(
+    app: AppContext,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("metrics").and(warp::get()).then(move || {
+        let app = app.clone();
+        async move {
+            let mut response = Response::new(Body::from(metrics::render(&app).await));
+            let headers = response.headers_mut();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; version=0.0.4"),
+            );
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            response
+        }
+    })
+}
+
+pub async fn start_file_server(app: AppContext, port: u16) {
+    let Err(e) = bind_file_server(&app, port).await else {
+        return;
+    };
+    eprintln!("⚠️ {}", e);
+    if config::is_port_available(port).await {
+        eprintln!("❌ File server is down, so the preview and API are too");
+        return;
+    }
+    let result = match pipeline::fall_back_port(&app, "file", port).await {
+        Ok(fallback) => bind_file_server(&app, fallback).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "❌ File server is down, so the preview and API are too: {}",
+            e
+        );
+    }
+}
+
+/// Serves the preview on `port`, replacing the running server. If the new port
+/// can't be bound the previous server keeps running, or is started again when it
+/// had to be stopped to free the port.
+pub async fn bind_file_server(app: &AppContext, port: u16) -> Result<(), String> {
+    let ouptut_dir: std::path::PathBuf = config::hls_output_dir(app);
+    std::fs::create_dir_all(&ouptut_dir).map_err(|e| e.to_string())?;
     let preview_dir = warp::fs::dir(ouptut_dir);
     println!("🗂️  Starting file server...");
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_headers(vec!["Content-Type", "Range"])
-        .allow_methods(vec!["GET", "HEAD", "OPTIONS"]);
+        .allow_headers(vec![
+            "Content-Type",
+            "Range",
+            "Authorization",
+            TOKEN_HEADER,
+            api::API_KEY_HEADER,
+        ])
+        .allow_methods(vec!["GET", "HEAD", "POST", "OPTIONS"]);
 
-    let routes = preview_dir.with(cors);
-    let app_state = app.state::<Arc<config::AppState>>();
+    let app_state = app.state();
+    let preview = authorize(app.clone())
+        .and(
+            ll_hls_routes(app_state.ll_hls.clone())
+                .or(flv_preview_routes(app.clone()))
+                .or(snapshot_route(app.clone()))
+                .or(metrics_route(app.clone()))
+                .or(preview_dir),
+        )
+        .map(set_token_cookie);
+    // the API checks its own key, so it sits in front of the preview token check
+    let routes = api::api_routes(app.clone())
+        .or(preview)
+        .recover(handle_rejection)
+        .with(cors);
+    // only this machine can reach the server unless LAN access is on
+    let ip = if app_state.preview_access.lock().await.lan_access {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        Ipv4Addr::LOCALHOST
+    };
+    let mut current = app_state.file_server.lock().await;
+    let mut previous = current.take();
+    let mut stopped_addr = None;
+    // the same port can only be bound again once the old server is gone
+    if previous
+        .as_ref()
+        .is_some_and(|handle| handle.addr.port() == port)
+    {
+        if let Some(handle) = previous.take() {
+            handle.task.abort();
+            let _ = handle.task.await;
+            stopped_addr = Some(handle.addr);
+        }
+    }
+    let (addr, server) = match warp::serve(routes.clone()).try_bind_ephemeral((ip, port)) {
+        Ok(bound) => bound,
+        Err(e) => {
+            // bring back the server that was stopped to free the port
+            if let Some(stopped_addr) = stopped_addr {
+                match warp::serve(routes).try_bind_ephemeral(stopped_addr) {
+                    Ok((addr, server)) => {
+                        eprintln!("⚠️ Keeping file server on {}", addr);
+                        previous = Some(ListenerHandle {
+                            addr,
+                            task: tokio::spawn(server),
+                        });
+                    }
+                    Err(restore_error) => {
+                        app_state.file_ready.store(false, Ordering::SeqCst);
+                        return Err(format!(
+                            "Failed to bind file server on port {}: {}. It could not be restarted on {} either ({}), so the preview and API are down",
+                            port, e, stopped_addr, restore_error
+                        ));
+                    }
+                }
+            }
+            *current = previous;
+            return Err(format!(
+                "Failed to bind file server on port {}: {}",
+                port, e
+            ));
+        }
+    };
+    if let Some(handle) = previous {
+        handle.task.abort();
+    }
+    *current = Some(ListenerHandle {
+        addr,
+        task: tokio::spawn(server),
+    });
     app_state.file_ready.store(true, Ordering::SeqCst);
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], port)) // or choose your port
-        .await;
-    println!("File server started at port {}", port)
+    println!("File server started at port {}", addr.port());
+    Ok(())
 }
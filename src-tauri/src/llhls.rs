@@ -0,0 +1,740 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+};
+
+/// Length of each part ffmpeg writes
+pub const PART_TARGET_SECS: f64 = 0.5;
+/// Parts ffmpeg keeps on disk, enough to cover every segment in the playlist
+pub const PARTS_ON_DISK: usize = 60;
+/// Segments are closed at the first keyframe after this long
+const SEGMENT_TARGET_SECS: f64 = 2.0;
+/// ...or cut anyway at this length when the source has a long keyframe interval
+const SEGMENT_MAX_SECS: f64 = 4.0;
+const PLAYLIST_SEGMENTS: usize = 6;
+/// Closed segments that still list their parts, older ones only list the whole segment
+const SEGMENTS_WITH_PARTS: usize = 2;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The spec asks servers to answer blocking requests within three target durations
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(3 * SEGMENT_MAX_SECS as u64);
+const PARTS_PLAYLIST: &str = "parts.m3u8";
+const INIT_FILE: &str = "init.mp4";
+
+#[derive(Debug, Clone)]
+struct Part {
+    uri: String,
+    duration: f64,
+    independent: bool,
+}
+
+#[derive(Debug)]
+struct Segment {
+    msn: u64,
+    parts: Vec<Part>,
+}
+
+impl Segment {
+    fn duration(&self) -> f64 {
+        self.parts.iter().map(|p| p.duration).sum()
+    }
+}
+
+/// Why a playlist can't be served.
+#[derive(Debug, PartialEq)]
+pub enum PlaylistError {
+    /// Nothing is being packaged, or nothing has been written yet
+    Unavailable,
+    /// A blocking reload asked for a part that didn't arrive in time
+    TimedOut,
+}
+
+#[derive(Debug)]
+struct Playlist {
+    dir: PathBuf,
+    /// Part of every media URI. ffmpeg reuses file names when the encoder restarts,
+    /// so this keeps players from caching media across runs.
+    session: String,
+    segments: VecDeque<Segment>,
+    current: Segment,
+    /// ffmpeg's sequence number of the first part we have not picked up yet
+    next_part_seq: u64,
+    /// None until init.mp4 has been read, then the video track id if there is one
+    video_track: Option<Option<u32>>,
+}
+
+impl Playlist {
+    fn new(dir: PathBuf, session: String) -> Self {
+        Self {
+            dir,
+            session,
+            segments: VecDeque::new(),
+            current: Segment {
+                msn: 0,
+                parts: vec![],
+            },
+            next_part_seq: 0,
+            video_track: None,
+        }
+    }
+
+    fn add_part(&mut self, part: Part) {
+        let duration = self.current.duration();
+        let should_close = !self.current.parts.is_empty()
+            && ((part.independent && duration >= SEGMENT_TARGET_SECS)
+                || duration + part.duration > SEGMENT_MAX_SECS);
+        if should_close {
+            let next = Segment {
+                msn: self.current.msn + 1,
+                parts: vec![],
+            };
+            let closed = std::mem::replace(&mut self.current, next);
+            self.segments.push_back(closed);
+            while self.segments.len() > PLAYLIST_SEGMENTS {
+                self.segments.pop_front();
+            }
+        }
+        self.current.parts.push(part);
+    }
+
+    /// Whether the playlist already contains what a blocking reload asked for.
+    fn has(&self, msn: u64, part: Option<usize>) -> bool {
+        match part {
+            Some(part) => {
+                self.current.msn > msn
+                    || (self.current.msn == msn && self.current.parts.len() > part)
+            }
+            None => self.current.msn > msn,
+        }
+    }
+
+    fn hinted_part(&self) -> String {
+        // ffmpeg names fmp4 parts after the playlist, e.g. parts12.m4s
+        format!("parts{}.m4s", self.next_part_seq)
+    }
+
+    fn render(&self) -> Option<String> {
+        if self.segments.is_empty() && self.current.parts.is_empty() {
+            return None;
+        }
+        let first_msn = self
+            .segments
+            .front()
+            .map(|s| s.msn)
+            .unwrap_or(self.current.msn);
+        let mut lines = vec![
+            "#EXTM3U".to_string(),
+            "#EXT-X-VERSION:9".to_string(),
+            format!("#EXT-X-TARGETDURATION:{}", SEGMENT_MAX_SECS.ceil() as u32),
+            format!("#EXT-X-PART-INF:PART-TARGET={:.3}", PART_TARGET_SECS),
+            format!(
+                "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}",
+                PART_TARGET_SECS * 3.0
+            ),
+            format!("#EXT-X-MEDIA-SEQUENCE:{}", first_msn),
+            format!("#EXT-X-MAP:URI=\"ll/{}/{}\"", self.session, INIT_FILE),
+        ];
+        let part_lines = |segment: &Segment, lines: &mut Vec<String>| {
+            for part in &segment.parts {
+                lines.push(format!(
+                    "#EXT-X-PART:DURATION={:.3},URI=\"ll/{}/{}\"{}",
+                    part.duration,
+                    self.session,
+                    part.uri,
+                    if part.independent {
+                        ",INDEPENDENT=YES"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        };
+        let listed_from = self.segments.len().saturating_sub(SEGMENTS_WITH_PARTS);
+        for (index, segment) in self.segments.iter().enumerate() {
+            if index >= listed_from {
+                part_lines(segment, &mut lines);
+            }
+            lines.push(format!("#EXTINF:{:.3},", segment.duration()));
+            lines.push(format!("ll/{}/segment_{}.m4s", self.session, segment.msn));
+        }
+        part_lines(&self.current, &mut lines);
+        lines.push(format!(
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"ll/{}/{}\"",
+            self.session,
+            self.hinted_part()
+        ));
+        lines.push(String::new());
+        Some(lines.join("\n"))
+    }
+}
+
+/// Builds an LL-HLS playlist from the short fMP4 parts ffmpeg writes. ffmpeg's
+/// hls muxer can't produce partial segment tags itself, so parts are grouped into
+/// segments here and whole segments are served by joining their parts.
+#[derive(Debug)]
+pub struct LlHls {
+    playlist: Mutex<Option<Playlist>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    updated: watch::Sender<()>,
+}
+
+impl Default for LlHls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LlHls {
+    pub fn new() -> Self {
+        Self {
+            playlist: Mutex::new(None),
+            task: Mutex::new(None),
+            updated: watch::channel(()).0,
+        }
+    }
+
+    /// Starts following the ffmpeg parts playlist in `dir`.
+    pub async fn start(self: &Arc<Self>, dir: PathBuf) {
+        let session = format!("{:08x}", rand::random::<u32>());
+        *self.playlist.lock().await = Some(Playlist::new(dir, session));
+        let this = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                if this.refresh().await {
+                    this.updated.send_replace(());
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+        if let Some(previous) = self.task.lock().await.replace(task) {
+            previous.abort();
+        }
+    }
+
+    pub async fn stop(&self) {
+        if let Some(task) = self.task.lock().await.take() {
+            task.abort();
+        }
+        *self.playlist.lock().await = None;
+        // wake blocked requests so they give up
+        self.updated.send_replace(());
+    }
+
+    pub async fn is_active(&self) -> bool {
+        self.playlist.lock().await.is_some()
+    }
+
+    /// Picks up parts ffmpeg has finished since the last call.
+    async fn refresh(&self) -> bool {
+        let mut guard = self.playlist.lock().await;
+        let Some(playlist) = guard.as_mut() else {
+            return false;
+        };
+        let Ok(text) = tokio::fs::read_to_string(playlist.dir.join(PARTS_PLAYLIST)).await else {
+            return false;
+        };
+        if playlist.video_track.is_none() {
+            let Ok(init) = tokio::fs::read(playlist.dir.join(INIT_FILE)).await else {
+                return false;
+            };
+            playlist.video_track = Some(video_track_id(&init));
+        }
+        let (first_seq, entries) = parse_media_playlist(&text);
+        let mut changed = false;
+        for (index, (duration, uri)) in entries.into_iter().enumerate() {
+            let seq = first_seq + index as u64;
+            if seq < playlist.next_part_seq {
+                continue;
+            }
+            let independent = match playlist.video_track.flatten() {
+                Some(track_id) => tokio::fs::read(playlist.dir.join(&uri))
+                    .await
+                    .map(|data| first_sample_is_sync(&data, track_id))
+                    .unwrap_or(false),
+                // audio only, every part can start playback
+                None => true,
+            };
+            playlist.add_part(Part {
+                uri,
+                duration,
+                independent,
+            });
+            playlist.next_part_seq = seq + 1;
+            changed = true;
+        }
+        changed
+    }
+
+    /// Renders the playlist. With `msn` set this is a blocking reload that waits
+    /// until that segment, or part of it, is available.
+    pub async fn playlist(
+        &self,
+        msn: Option<u64>,
+        part: Option<usize>,
+    ) -> Result<String, PlaylistError> {
+        if let Some(msn) = msn {
+            let mut updates = self.updated.subscribe();
+            let deadline = Instant::now() + BLOCK_TIMEOUT;
+            loop {
+                match self.playlist.lock().await.as_ref() {
+                    None => return Err(PlaylistError::Unavailable),
+                    Some(playlist) if playlist.has(msn, part) => break,
+                    Some(_) => {}
+                }
+                match tokio::time::timeout_at(deadline.into(), updates.changed()).await {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(_)) => return Err(PlaylistError::Unavailable),
+                    // the spec wants an error here, not a playlist without the part
+                    Err(_) => return Err(PlaylistError::TimedOut),
+                }
+            }
+        }
+        self.playlist
+            .lock()
+            .await
+            .as_ref()
+            .and_then(Playlist::render)
+            .ok_or(PlaylistError::Unavailable)
+    }
+
+    /// Returns the init section, a part, or a whole segment of the current
+    /// session. A request for the hinted part waits until ffmpeg has written it.
+    pub async fn file(&self, session: &str, name: &str) -> Option<Vec<u8>> {
+        if name.contains(|c| c == '/' || c == '\\') || name.contains("..") {
+            return None;
+        }
+        let segment_msn = name
+            .strip_prefix("segment_")
+            .and_then(|n| n.strip_suffix(".m4s"))
+            .and_then(|n| n.parse::<u64>().ok());
+        if let Some(msn) = segment_msn {
+            let paths: Vec<PathBuf> = {
+                let guard = self.playlist.lock().await;
+                let playlist = guard.as_ref().filter(|p| p.session == session)?;
+                let segment = playlist.segments.iter().find(|s| s.msn == msn)?;
+                segment
+                    .parts
+                    .iter()
+                    .map(|p| playlist.dir.join(&p.uri))
+                    .collect()
+            };
+            // fMP4 fragments can be concatenated as they are
+            let mut data = vec![];
+            for path in paths {
+                data.extend(tokio::fs::read(path).await.ok()?);
+            }
+            return Some(data);
+        }
+
+        let (path, is_hinted) = {
+            let guard = self.playlist.lock().await;
+            let playlist = guard.as_ref().filter(|p| p.session == session)?;
+            (playlist.dir.join(name), playlist.hinted_part() == name)
+        };
+        let mut updates = self.updated.subscribe();
+        let deadline = Instant::now() + BLOCK_TIMEOUT;
+        loop {
+            if let Ok(data) = tokio::fs::read(&path).await {
+                return Some(data);
+            }
+            if !is_hinted {
+                return None;
+            }
+            let changed = tokio::time::timeout_at(deadline.into(), updates.changed()).await;
+            if !matches!(changed, Ok(Ok(()))) || !self.is_active().await {
+                return None;
+            }
+        }
+    }
+}
+
+/// Returns the media sequence of an ffmpeg playlist and the (duration, uri) of every entry.
+pub fn parse_media_playlist(text: &str) -> (u64, Vec<(f64, String)>) {
+    let mut first_seq = 0;
+    let mut entries = vec![];
+    let mut duration = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(seq) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            first_seq = seq.parse().unwrap_or(0);
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            duration = info.split(',').next().and_then(|d| d.parse::<f64>().ok());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            if let Some(duration) = duration.take() {
+                entries.push((duration, line.to_string()));
+            }
+        }
+    }
+    (first_seq, entries)
+}
+
+/// Iterates the ISO BMFF boxes in `data` as (type, payload).
+fn boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let data = rest;
+        let size = read_u32(data, 0)? as usize;
+        let kind = data.get(4..8)?;
+        let (header, size) = match size {
+            // 64 bit size follows the type
+            1 => (
+                16,
+                u64::from_be_bytes(data.get(8..16)?.try_into().ok()?) as usize,
+            ),
+            // box runs to the end of its parent
+            0 => (8, data.len()),
+            size => (8, size),
+        };
+        if size < header || size > data.len() {
+            return None;
+        }
+        rest = &data[size..];
+        Some((kind, &data[header..size]))
+    })
+}
+
+fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(k, _)| *k == &kind[..])
+        .map(|(_, payload)| payload)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Finds the id of the video track in an fMP4 init section.
+fn video_track_id(init: &[u8]) -> Option<u32> {
+    let moov = find_box(init, b"moov")?;
+    boxes(moov)
+        .filter(|(kind, _)| *kind == &b"trak"[..])
+        .find_map(|(_, trak)| {
+            let hdlr = find_box(find_box(trak, b"mdia")?, b"hdlr")?;
+            if hdlr.get(8..12)? != b"vide" {
+                return None;
+            }
+            let tkhd = find_box(trak, b"tkhd")?;
+            // version 1 uses 64 bit creation and modification times
+            let offset = if *tkhd.first()? == 1 { 20 } else { 12 };
+            read_u32(tkhd, offset)
+        })
+}
+
+/// Whether the first sample of `track_id` in an fMP4 fragment is a keyframe.
+fn first_sample_is_sync(fragment: &[u8], track_id: u32) -> bool {
+    const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+    let Some(moof) = find_box(fragment, b"moof") else {
+        return false;
+    };
+    for (kind, traf) in boxes(moof) {
+        if kind != b"traf" {
+            continue;
+        }
+        let Some(tfhd) = find_box(traf, b"tfhd") else {
+            continue;
+        };
+        if read_u32(tfhd, 4) != Some(track_id) {
+            continue;
+        }
+        let tfhd_flags = read_u32(tfhd, 0).unwrap_or(0) & 0x00FF_FFFF;
+        // optional tfhd fields come after the track id in this order
+        let mut offset = 8;
+        for (flag, len) in [(0x01, 8), (0x02, 4), (0x08, 4), (0x10, 4)] {
+            if tfhd_flags & flag != 0 {
+                offset += len;
+            }
+        }
+        let default_flags = (tfhd_flags & 0x20 != 0)
+            .then(|| read_u32(tfhd, offset))
+            .flatten();
+
+        let Some(trun) = find_box(traf, b"trun") else {
+            return false;
+        };
+        let trun_flags = read_u32(trun, 0).unwrap_or(0) & 0x00FF_FFFF;
+        // skip version/flags and sample_count, then the optional data offset
+        let mut offset = if trun_flags & 0x01 != 0 { 12 } else { 8 };
+        let sample_flags = if trun_flags & 0x04 != 0 {
+            read_u32(trun, offset)
+        } else if trun_flags & 0x400 != 0 {
+            for flag in [0x100, 0x200] {
+                if trun_flags & flag != 0 {
+                    offset += 4;
+                }
+            }
+            read_u32(trun, offset)
+        } else {
+            default_flags
+        };
+        return sample_flags.is_some_and(|flags| flags & SAMPLE_IS_NON_SYNC == 0);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn full_box(kind: &[u8; 4], version: u8, flags: u32, fields: &[u32]) -> Vec<u8> {
+        let mut payload = (((version as u32) << 24) | flags).to_be_bytes().to_vec();
+        for field in fields {
+            payload.extend_from_slice(&field.to_be_bytes());
+        }
+        mp4_box(kind, &payload)
+    }
+
+    fn trak(handler: &[u8; 4], tkhd: Vec<u8>) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0; 12]);
+        let mdia = mp4_box(b"mdia", &mp4_box(b"hdlr", &hdlr));
+        mp4_box(b"trak", &[tkhd, mdia].concat())
+    }
+
+    fn fragment(tfhd: Vec<u8>, trun: Vec<u8>) -> Vec<u8> {
+        let traf = mp4_box(b"traf", &[tfhd, trun].concat());
+        let moof = mp4_box(b"moof", &[full_box(b"mfhd", 0, 0, &[1]), traf].concat());
+        [moof, mp4_box(b"mdat", &[0; 16])].concat()
+    }
+
+    const SYNC: u32 = 0x0200_0000;
+    const NON_SYNC: u32 = 0x0101_0000;
+
+    fn part(seq: u64, independent: bool) -> Part {
+        Part {
+            uri: format!("parts{}.m4s", seq),
+            duration: 0.5,
+            independent,
+        }
+    }
+
+    /// One closed segment of four parts and two parts of the next one.
+    fn playlist() -> Playlist {
+        let mut playlist = Playlist::new(PathBuf::new(), "s1".into());
+        for seq in 0..6 {
+            playlist.add_part(part(seq, seq % 4 == 0));
+        }
+        playlist.next_part_seq = 6;
+        playlist
+    }
+
+    #[test]
+    fn iterates_boxes_with_large_and_open_ended_sizes() {
+        let mut large = 1u32.to_be_bytes().to_vec();
+        large.extend_from_slice(b"free");
+        large.extend_from_slice(&20u64.to_be_bytes());
+        large.extend_from_slice(&[7; 4]);
+        let mut open_ended = 0u32.to_be_bytes().to_vec();
+        open_ended.extend_from_slice(b"mdat");
+        open_ended.extend_from_slice(&[9; 3]);
+        let data = [mp4_box(b"ftyp", b"isom"), large, open_ended].concat();
+
+        let found: Vec<(&[u8], &[u8])> = boxes(&data).collect();
+        assert_eq!(
+            found,
+            vec![
+                (&b"ftyp"[..], &b"isom"[..]),
+                (&b"free"[..], &[7u8; 4][..]),
+                (&b"mdat"[..], &[9u8; 3][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_a_truncated_box() {
+        let mut data = mp4_box(b"moov", &[0; 8]);
+        data.truncate(12);
+        assert_eq!(boxes(&data).count(), 0);
+        assert_eq!(boxes(&[0, 0, 0]).count(), 0);
+    }
+
+    #[test]
+    fn finds_boxes_by_type() {
+        let moov = mp4_box(b"moov", &mp4_box(b"mvhd", &[1; 4]));
+        let data = [mp4_box(b"ftyp", b"iso6"), moov, mp4_box(b"mdat", &[2; 8])].concat();
+        assert_eq!(find_box(&data, b"ftyp"), Some(&b"iso6"[..]));
+        assert_eq!(find_box(&data, b"mdat"), Some(&[2u8; 8][..]));
+        let moov = find_box(&data, b"moov").unwrap();
+        assert_eq!(find_box(moov, b"mvhd"), Some(&[1u8; 4][..]));
+        // only the top level is searched
+        assert_eq!(find_box(&data, b"mvhd"), None);
+    }
+
+    #[test]
+    fn finds_the_video_track() {
+        let audio = trak(b"soun", full_box(b"tkhd", 0, 3, &[0, 0, 1]));
+        let video = trak(b"vide", full_box(b"tkhd", 0, 3, &[0, 0, 2]));
+        let init = [
+            mp4_box(b"ftyp", b"iso6"),
+            mp4_box(b"moov", &[audio.clone(), video].concat()),
+        ]
+        .concat();
+        assert_eq!(video_track_id(&init), Some(2));
+
+        // version 1 has 64 bit times before the track id
+        let video = trak(b"vide", full_box(b"tkhd", 1, 3, &[0, 0, 0, 0, 7]));
+        let init = mp4_box(b"moov", &[audio.clone(), video].concat());
+        assert_eq!(video_track_id(&init), Some(7));
+
+        let audio_only = mp4_box(b"moov", &audio);
+        assert_eq!(video_track_id(&audio_only), None);
+    }
+
+    #[test]
+    fn reads_first_sample_flags() {
+        // data offset and first sample flags in the trun
+        let tfhd = full_box(b"tfhd", 0, 0x02_0000, &[1]);
+        let keyframe = fragment(tfhd.clone(), full_box(b"trun", 0, 0x05, &[3, 100, SYNC]));
+        let delta = fragment(tfhd, full_box(b"trun", 0, 0x05, &[3, 100, NON_SYNC]));
+        assert!(first_sample_is_sync(&keyframe, 1));
+        assert!(!first_sample_is_sync(&delta, 1));
+        assert!(!first_sample_is_sync(&keyframe, 2));
+    }
+
+    #[test]
+    fn falls_back_to_tfhd_default_flags() {
+        // sample description index (0x02) comes before the default flags (0x20)
+        let trun = full_box(b"trun", 0, 0x01, &[3, 100]);
+        let sync = fragment(full_box(b"tfhd", 0, 0x22, &[1, 1, SYNC]), trun.clone());
+        let non_sync = fragment(full_box(b"tfhd", 0, 0x22, &[1, 1, NON_SYNC]), trun);
+        assert!(first_sample_is_sync(&sync, 1));
+        assert!(!first_sample_is_sync(&non_sync, 1));
+    }
+
+    #[test]
+    fn reads_per_sample_flags() {
+        // duration and size come before each sample's flags
+        let tfhd = full_box(b"tfhd", 0, 0, &[1]);
+        let trun = full_box(b"trun", 0, 0x700, &[2, 40, 1000, SYNC, 40, 800, NON_SYNC]);
+        assert!(first_sample_is_sync(&fragment(tfhd, trun), 1));
+        assert!(!first_sample_is_sync(&mp4_box(b"mdat", &[0; 8]), 1));
+    }
+
+    #[test]
+    fn parses_the_ffmpeg_parts_playlist() {
+        let text = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:12\n#EXT-X-MAP:URI=\"init.mp4\"\n\
+                    #EXTINF:0.500000,\nparts12.m4s\n#EXTINF:0.480000,\nparts13.m4s\n";
+        let (first_seq, entries) = parse_media_playlist(text);
+        assert_eq!(first_seq, 12);
+        assert_eq!(
+            entries,
+            vec![
+                (0.5, "parts12.m4s".to_string()),
+                (0.48, "parts13.m4s".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_parts_and_preload_hint() {
+        let expected = [
+            "#EXTM3U",
+            "#EXT-X-VERSION:9",
+            "#EXT-X-TARGETDURATION:4",
+            "#EXT-X-PART-INF:PART-TARGET=0.500",
+            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.500",
+            "#EXT-X-MEDIA-SEQUENCE:0",
+            "#EXT-X-MAP:URI=\"ll/s1/init.mp4\"",
+            "#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts0.m4s\",INDEPENDENT=YES",
+            "#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts1.m4s\"",
+            "#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts2.m4s\"",
+            "#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts3.m4s\"",
+            "#EXTINF:2.000,",
+            "ll/s1/segment_0.m4s",
+            "#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts4.m4s\",INDEPENDENT=YES",
+            "#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts5.m4s\"",
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"ll/s1/parts6.m4s\"",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(playlist().render().as_deref(), Some(expected.as_str()));
+        assert_eq!(Playlist::new(PathBuf::new(), "s1".into()).render(), None);
+    }
+
+    #[test]
+    fn only_recent_segments_list_their_parts() {
+        let mut playlist = playlist();
+        for seq in 6..13 {
+            playlist.add_part(part(seq, seq % 4 == 0));
+        }
+        let rendered = playlist.render().unwrap();
+        assert_eq!(playlist.segments.len(), 3);
+        assert!(!rendered.contains("parts0.m4s"));
+        assert!(rendered.contains("ll/s1/segment_0.m4s"));
+        assert!(rendered.contains("parts4.m4s"));
+    }
+
+    #[test]
+    fn cuts_long_segments_without_keyframes() {
+        let mut playlist = Playlist::new(PathBuf::new(), "s1".into());
+        for seq in 0..9 {
+            playlist.add_part(part(seq, seq == 0));
+        }
+        assert_eq!(playlist.segments.len(), 1);
+        assert_eq!(playlist.segments[0].duration(), SEGMENT_MAX_SECS);
+        assert!(playlist.has(0, None));
+        assert!(playlist.has(1, Some(0)));
+        assert!(!playlist.has(1, Some(1)));
+    }
+
+    async fn running(playlist: Playlist) -> Arc<LlHls> {
+        let ll_hls = Arc::new(LlHls::new());
+        *ll_hls.playlist.lock().await = Some(playlist);
+        ll_hls
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blocking_reload_waits_for_the_part() {
+        let ll_hls = running(playlist()).await;
+        let writer = ll_hls.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if let Some(playlist) = writer.playlist.lock().await.as_mut() {
+                playlist.add_part(part(6, false));
+                playlist.next_part_seq = 7;
+            }
+            writer.updated.send_replace(());
+        });
+
+        let body = ll_hls.playlist(Some(1), Some(2)).await.unwrap();
+        assert!(body.contains("#EXT-X-PART:DURATION=0.500,URI=\"ll/s1/parts6.m4s\"\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blocking_reload_times_out_with_an_error() {
+        let ll_hls = running(playlist()).await;
+        assert_eq!(
+            ll_hls.playlist(Some(1), Some(2)).await,
+            Err(PlaylistError::TimedOut)
+        );
+        // a part that is already there is answered at once
+        assert!(ll_hls.playlist(Some(1), Some(1)).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopping_releases_blocked_reloads() {
+        let ll_hls = running(playlist()).await;
+        let stopper = ll_hls.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            stopper.stop().await;
+        });
+        assert_eq!(
+            ll_hls.playlist(Some(5), None).await,
+            Err(PlaylistError::Unavailable)
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{context::AppContext, db, rtmp};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
+};
+use sysinfo::{ProcessesToUpdate, System};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Per-relay counters, kept across restarts of the same target.
+#[derive(Debug, Default)]
+pub struct RelayCounters {
+    /// Bytes written to the relay's ffmpeg
+    pub bytes: AtomicU64,
+    pub starts: AtomicU64,
+    pub failures: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    /// (bytes at the last sample, kbps over the last interval) per relay
+    relays: HashMap<i64, (u64, f64)>,
+    cpu_percent: f64,
+    memory_bytes: u64,
+}
+
+/// Counters and samples behind the Prometheus `/metrics` endpoint.
+#[derive(Debug)]
+pub struct Metrics {
+    started_at: Instant,
+    relays: StdMutex<HashMap<i64, Arc<RelayCounters>>>,
+    /// ffmpeg's reported encoding speed, 1.0 is realtime
+    encoder_speed: StdMutex<Option<f64>>,
+    samples: StdMutex<Samples>,
+    system: StdMutex<System>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            relays: StdMutex::new(HashMap::new()),
+            encoder_speed: StdMutex::new(None),
+            samples: StdMutex::new(Samples::default()),
+            system: StdMutex::new(System::new()),
+        }
+    }
+
+    pub fn relay(&self, id: i64) -> Arc<RelayCounters> {
+        self.relays.lock().unwrap().entry(id).or_default().clone()
+    }
+
+    pub fn set_encoder_speed(&self, speed: Option<f64>) {
+        *self.encoder_speed.lock().unwrap() = speed;
+    }
+
+    /// Turns relay byte counters into bitrates and refreshes process usage.
+    fn sample(&self, interval: Duration) {
+        let relays = self.relays.lock().unwrap();
+        let mut samples = self.samples.lock().unwrap();
+        for (id, counters) in relays.iter() {
+            let bytes = counters.bytes.load(Ordering::Relaxed);
+            let previous = samples.relays.get(id).map(|(b, _)| *b).unwrap_or(bytes);
+            let kbps =
+                bytes.saturating_sub(previous) as f64 * 8.0 / 1000.0 / interval.as_secs_f64();
+            samples.relays.insert(*id, (bytes, kbps));
+        }
+        drop(relays);
+        let Ok(pid) = sysinfo::get_current_pid() else {
+            return;
+        };
+        let mut system = self.system.lock().unwrap();
+        system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        if let Some(process) = system.process(pid) {
+            samples.cpu_percent = process.cpu_usage() as f64;
+            samples.memory_bytes = process.memory();
+        }
+    }
+}
+
+/// ffmpeg progress lines end in e.g. `speed=1.02x`, or `speed=N/A` before it knows.
+pub fn parse_speed(line: &str) -> Option<f64> {
+    let (_, rest) = line.rsplit_once("speed=")?;
+    rest.trim().trim_end_matches('x').parse().ok()
+}
+
+pub fn start_sampler(app: AppContext) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+            app.state().metrics.sample(SAMPLE_INTERVAL);
+        }
+    });
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn value(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+            .collect();
+        if labels.is_empty() {
+            let _ = writeln!(self.out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(self.out, "{}{{{}}} {}", name, labels.join(","), value);
+        }
+    }
+
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: f64) {
+        self.family(name, kind, help);
+        self.value(name, &[], value);
+    }
+}
+
+/// Renders every metric in the Prometheus text format.
+pub async fn render(app: &AppContext) -> String {
+    let state = app.state();
+    let metrics = &state.metrics;
+    let mut out = Exposition { out: String::new() };
+
+    out.single(
+        "duplicast_uptime_seconds",
+        "gauge",
+        "Seconds since the app started",
+        metrics.started_at.elapsed().as_secs_f64(),
+    );
+    let (cpu_percent, memory_bytes, relay_kbps) = {
+        let samples = metrics.samples.lock().unwrap();
+        let relay_kbps: HashMap<i64, f64> = samples
+            .relays
+            .iter()
+            .map(|(id, (_, kbps))| (*id, *kbps))
+            .collect();
+        (samples.cpu_percent, samples.memory_bytes, relay_kbps)
+    };
+    out.single(
+        "duplicast_process_cpu_percent",
+        "gauge",
+        "CPU used by the app process, 100 is one core",
+        cpu_percent,
+    );
+    out.single(
+        "duplicast_process_memory_bytes",
+        "gauge",
+        "Resident memory of the app process",
+        memory_bytes as f64,
+    );
+
+    let stats = state.source_stats.lock().await.clone();
+    out.single(
+        "duplicast_stream_active",
+        "gauge",
+        "Whether a source is publishing",
+        stats.is_some() as u8 as f64,
+    );
+    if let Some(stats) = stats {
+        out.single(
+            "duplicast_ingest_bitrate_kbps",
+            "gauge",
+            "Bitrate of the incoming stream",
+            stats.bitrate_kbps,
+        );
+        out.single(
+            "duplicast_ingest_bytes_total",
+            "counter",
+            "Media bytes received from the current publisher",
+            stats.total_bytes as f64,
+        );
+        out.single(
+            "duplicast_ingest_fps",
+            "gauge",
+            "Frame rate of the incoming stream",
+            stats.fps,
+        );
+    }
+    out.single(
+        "duplicast_ingest_rejected_connections_total",
+        "counter",
+        "Ingest connections refused by access rules, bans or a busy ingest",
+        state.rejected_connections.load(Ordering::SeqCst) as f64,
+    );
+    if let Some(speed) = *metrics.encoder_speed.lock().unwrap() {
+        out.single(
+            "duplicast_encoder_speed",
+            "gauge",
+            "Encoding speed reported by ffmpeg, 1 is realtime",
+            speed,
+        );
+    }
+
+    let targets = db::get_relay_targets(db::get_db_pool())
+        .await
+        .unwrap_or_default();
+    let tags: HashMap<i64, String> = targets.iter().map(|t| (t.id, t.tag.clone())).collect();
+    let tag = |id: i64| tags.get(&id).cloned().unwrap_or_else(|| id.to_string());
+    let running: Vec<i64> = state.relays.lock().await.keys().copied().collect();
+    let counters: Vec<(i64, Arc<RelayCounters>)> = metrics
+        .relays
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, counters)| (*id, counters.clone()))
+        .collect();
+
+    out.family(
+        "duplicast_relay_up",
+        "gauge",
+        "Whether the relay is running",
+    );
+    for target in &targets {
+        let up = running.contains(&target.id) as u8 as f64;
+        // tags are free text and may repeat, the id tells relays apart
+        out.value(
+            "duplicast_relay_up",
+            &[("id", &target.id.to_string()), ("tag", &target.tag)],
+            up,
+        );
+    }
+    out.family(
+        "duplicast_relay_bitrate_kbps",
+        "gauge",
+        "Bitrate sent to the relay",
+    );
+    for (id, _) in &counters {
+        let kbps = relay_kbps.get(id).copied().unwrap_or(0.0);
+        out.value(
+            "duplicast_relay_bitrate_kbps",
+            &[("id", &id.to_string()), ("tag", &tag(*id))],
+            kbps,
+        );
+    }
+    let relay_counters: [(&str, &str, fn(&RelayCounters) -> u64); 3] = [
+        (
+            "duplicast_relay_bytes_total",
+            "Bytes sent to the relay",
+            |c| c.bytes.load(Ordering::Relaxed),
+        ),
+        (
+            "duplicast_relay_reconnects_total",
+            "Times the relay was started again after its first start",
+            |c| c.starts.load(Ordering::Relaxed).saturating_sub(1),
+        ),
+        (
+            "duplicast_relay_failures_total",
+            "Times the relay's ffmpeg exited with an error",
+            |c| c.failures.load(Ordering::Relaxed),
+        ),
+    ];
+    for (name, help, read) in relay_counters {
+        out.family(name, "counter", help);
+        for (id, counters) in &counters {
+            out.value(
+                name,
+                &[("id", &id.to_string()), ("tag", &tag(*id))],
+                read(counters) as f64,
+            );
+        }
+    }
+
+    out.family(
+        "duplicast_fanout_queue_depth",
+        "gauge",
+        "Chunks waiting in a fanout consumer's queue",
+    );
+    for (id, tx) in state.relay_channels.lock().await.iter() {
+        let depth = tx.max_capacity() - tx.capacity();
+        out.value(
+            "duplicast_fanout_queue_depth",
+            &[
+                ("consumer", "relay"),
+                ("id", &id.to_string()),
+                ("tag", &tag(*id)),
+            ],
+            depth as f64,
+        );
+    }
+    for (filter, rendition) in state.renditions.lock().await.iter() {
+        let depth = rendition.input.max_capacity() - rendition.input.capacity();
+        out.value(
+            "duplicast_fanout_queue_depth",
+            &[
+                ("consumer", "rendition"),
+                ("tag", &rtmp::rendition_id(filter)),
+            ],
+            depth as f64,
+        );
+        for (id, tx) in rendition.channels.lock().await.iter() {
+            let depth = tx.max_capacity() - tx.capacity();
+            out.value(
+                "duplicast_fanout_queue_depth",
+                &[
+                    ("consumer", "relay"),
+                    ("id", &id.to_string()),
+                    ("tag", &tag(*id)),
+                ],
+                depth as f64,
+            );
+        }
+    }
+    for (name, tx) in state.feed_taps.lock().await.iter() {
+        let depth = tx.max_capacity() - tx.capacity();
+        out.value(
+            "duplicast_fanout_queue_depth",
+            &[("consumer", "tap"), ("tag", name)],
+            depth as f64,
+        );
+    }
+    out.out
+}
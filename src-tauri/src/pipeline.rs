@@ -0,0 +1,144 @@
+use crate::{
+    config::{self, PortFallback, PortInfo},
+    context::AppContext,
+    db,
+    events::{AppEvents, EventPublisher},
+    file_server, metrics, rtmp, webhooks,
+};
+
+/// Starts the background work that only needs the shared state and the DB.
+pub fn start_services(app: &AppContext) {
+    metrics::start_sampler(app.clone());
+    webhooks::start_dispatcher(app.clone());
+}
+
+/// Creates the log directory and clears preview output left from the last run.
+pub fn prepare_dirs(app: &AppContext) {
+    let log_dir = config::log_output_dir(app);
+    if !log_dir.exists() {
+        std::fs::create_dir_all(&log_dir).expect("Failed to create log directory");
+    }
+    let hls_dir = config::hls_output_dir(app);
+    if hls_dir.exists() {
+        std::fs::remove_dir_all(&hls_dir).expect("Failed to remove hls_output_dir");
+    }
+}
+
+fn announce_port_fallback(app: &AppContext, fallback: &PortFallback) {
+    eprintln!(
+        "⚠️ {} port {} is in use, using {} instead",
+        fallback.service, fallback.requested, fallback.port
+    );
+    app.publish(AppEvents::PortFallback, fallback);
+}
+
+/// Moves a service to a free port when its own was taken between the startup
+/// check and the bind, and saves the new one.
+pub async fn fall_back_port(
+    app: &AppContext,
+    service: &'static str,
+    requested: u16,
+) -> Result<u16, String> {
+    let state = app.state();
+    let mut ports = state.ports.lock().await;
+    let other = match service {
+        "rtmp" => ports.file_port,
+        _ => ports.rtmp_port,
+    };
+    let mut port = None;
+    let mut start = requested.checked_add(1);
+    while let Some(from) = start {
+        let found = config::find_available_port(from)
+            .await
+            .map_err(|e| e.to_string())?;
+        if found != other {
+            port = Some(found);
+            break;
+        }
+        start = found.checked_add(1);
+    }
+    let port = port.ok_or("No available ports found")?;
+    match service {
+        "rtmp" => ports.rtmp_port = port,
+        _ => ports.file_port = port,
+    }
+    if let Err(e) = config::save_ports(&ports, db::get_db_pool()).await {
+        eprintln!("⚠️ Failed to save the {} port: {}", service, e);
+    }
+    let fallback = PortFallback {
+        service,
+        requested,
+        port,
+    };
+    announce_port_fallback(app, &fallback);
+    state.port_fallbacks.lock().await.push(fallback);
+    Ok(port)
+}
+
+/// Loads every stored setting into the shared state and settles on the ports,
+/// moving off any that are taken. Expects the DB to be open.
+pub async fn load_settings(app: &AppContext) -> PortInfo {
+    let db_pool = db::get_db_pool();
+    let app_state = app.state();
+    if let Err(e) = app_state.redactor.reload(db_pool).await {
+        eprintln!("⚠️ Failed to load stream keys for log redaction: {}", e);
+    }
+    if let Err(e) = app_state.reload_access_rules(db_pool).await {
+        eprintln!("⚠️ Failed to load ingest access rules: {}", e);
+    }
+    let port_info = config::get_or_init_ports(db_pool)
+        .await
+        .expect("❌ Failed to init ports");
+    let (port_info, fallbacks) = config::ensure_ports_available(port_info, db_pool)
+        .await
+        .expect("❌ Failed to find free ports");
+    for fallback in &fallbacks {
+        announce_port_fallback(app, fallback);
+    }
+    *app_state.port_fallbacks.lock().await = fallbacks;
+    *app_state.ports.lock().await = port_info.clone();
+    let settings = db::load_encoder_settings(db_pool)
+        .await
+        .unwrap_or_else(|_| db::default_encoder_settings());
+    *app_state.encoder_settings.lock().await = settings;
+    let overlays = db::load_overlay_settings(db_pool)
+        .await
+        .unwrap_or_else(|_| db::default_overlay_settings());
+    if let Err(e) = config::write_overlay_text(app, &overlays.text_content) {
+        eprintln!("⚠️ Failed to write overlay text: {}", e);
+    }
+    *app_state.overlay_settings.lock().await = overlays;
+    *app_state.ingest_settings.lock().await = db::load_ingest_settings(db_pool)
+        .await
+        .unwrap_or_else(|_| db::default_ingest_settings());
+    let preview_access = match db::load_preview_access_settings(db_pool).await {
+        Ok(settings) => settings,
+        Err(_) => {
+            // keep the first generated token across restarts
+            let settings = db::default_preview_access_settings();
+            if let Err(e) = db::save_preview_access_settings(&settings, db_pool).await {
+                eprintln!("⚠️ Failed to save preview access token: {}", e);
+            }
+            settings
+        }
+    };
+    *app_state.preview_access.lock().await = preview_access;
+    let api_settings = match db::load_api_settings(db_pool).await {
+        Ok(settings) => settings,
+        Err(_) => {
+            let settings = db::default_api_settings();
+            if let Err(e) = db::save_api_settings(&settings, db_pool).await {
+                eprintln!("⚠️ Failed to save API key: {}", e);
+            }
+            settings
+        }
+    };
+    *app_state.api_settings.lock().await = api_settings;
+    port_info
+}
+
+/// Starts the RTMP ingest and the file server, which carries the preview and the API.
+pub fn serve(app: &AppContext, ports: &PortInfo) {
+    tokio::spawn(rtmp::init_rtmp_server(app.clone(), ports.rtmp_port));
+    tokio::spawn(file_server::start_file_server(app.clone(), ports.file_port));
+}
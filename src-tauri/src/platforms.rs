@@ -0,0 +1,403 @@
+use serde::Serialize;
+
+use crate::{
+    db::{EncoderSettings, RelayTarget},
+    rtmp::SourceStats,
+};
+
+/// Slack on a measured keyframe interval, so frame timing jitter doesn't warn.
+const KEYFRAME_TOLERANCE_SECS: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Rtmp,
+    Rtmps,
+}
+
+impl Protocol {
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            Protocol::Rtmp => "rtmp",
+            Protocol::Rtmps => "rtmps",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestServer {
+    pub name: &'static str,
+    pub url: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Platform {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub protocol: Protocol,
+    pub ingest_servers: &'static [IngestServer],
+    /// Recommended maximum video bitrate in kbps
+    pub max_video_bitrate: u32,
+    /// Recommended maximum audio bitrate in kbps
+    pub max_audio_bitrate: u32,
+    pub max_framerate: u32,
+    /// Keyframe interval in seconds the platform expects
+    pub keyframe_interval: u32,
+    pub video_codecs: &'static [&'static str],
+    pub audio_codecs: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlatformWarning {
+    pub target_id: Option<i64>,
+    pub platform: &'static str,
+    pub message: String,
+}
+
+static PLATFORMS: &[Platform] = &[
+    Platform {
+        id: "twitch",
+        name: "Twitch",
+        protocol: Protocol::Rtmp,
+        ingest_servers: &[
+            IngestServer {
+                name: "Auto (closest)",
+                url: "rtmp://live.twitch.tv/app",
+            },
+            IngestServer {
+                name: "Auto (secure)",
+                url: "rtmps://live.twitch.tv:443/app",
+            },
+        ],
+        max_video_bitrate: 6000,
+        max_audio_bitrate: 160,
+        max_framerate: 60,
+        keyframe_interval: 2,
+        video_codecs: &["h264"],
+        audio_codecs: &["aac"],
+    },
+    Platform {
+        id: "youtube",
+        name: "YouTube",
+        protocol: Protocol::Rtmp,
+        ingest_servers: &[
+            IngestServer {
+                name: "Primary",
+                url: "rtmp://a.rtmp.youtube.com/live2",
+            },
+            IngestServer {
+                name: "Backup",
+                url: "rtmp://b.rtmp.youtube.com/live2?backup=1",
+            },
+            IngestServer {
+                name: "Primary (secure)",
+                url: "rtmps://a.rtmps.youtube.com/live2",
+            },
+        ],
+        max_video_bitrate: 12000,
+        max_audio_bitrate: 384,
+        max_framerate: 60,
+        keyframe_interval: 2,
+        video_codecs: &["h264"],
+        audio_codecs: &["aac", "mp3"],
+    },
+    Platform {
+        id: "kick",
+        name: "Kick",
+        protocol: Protocol::Rtmps,
+        ingest_servers: &[IngestServer {
+            name: "Global",
+            url: "rtmps://fa723fc1b171.global-contribute.live-video.net:443/app",
+        }],
+        max_video_bitrate: 8000,
+        max_audio_bitrate: 160,
+        max_framerate: 60,
+        keyframe_interval: 2,
+        video_codecs: &["h264"],
+        audio_codecs: &["aac"],
+    },
+    Platform {
+        id: "facebook",
+        name: "Facebook Live",
+        protocol: Protocol::Rtmps,
+        ingest_servers: &[IngestServer {
+            name: "Default",
+            url: "rtmps://live-api-s.facebook.com:443/rtmp",
+        }],
+        max_video_bitrate: 6000,
+        max_audio_bitrate: 256,
+        max_framerate: 30,
+        keyframe_interval: 2,
+        video_codecs: &["h264"],
+        audio_codecs: &["aac"],
+    },
+    Platform {
+        id: "trovo",
+        name: "Trovo",
+        protocol: Protocol::Rtmp,
+        ingest_servers: &[IngestServer {
+            name: "Default",
+            url: "rtmp://livepush.trovo.live/live",
+        }],
+        max_video_bitrate: 8000,
+        max_audio_bitrate: 160,
+        max_framerate: 60,
+        keyframe_interval: 2,
+        video_codecs: &["h264"],
+        audio_codecs: &["aac"],
+    },
+];
+
+impl Platform {
+    /// Whether any catalog server uses rtmps.
+    fn supports_tls(&self) -> bool {
+        self.protocol == Protocol::Rtmps
+            || self
+                .ingest_servers
+                .iter()
+                .any(|s| s.url.starts_with("rtmps://"))
+    }
+
+    fn schemes(&self) -> Vec<&'static str> {
+        match self.protocol {
+            Protocol::Rtmp if self.supports_tls() => vec!["rtmp", "rtmps"],
+            protocol => vec![protocol.scheme()],
+        }
+    }
+}
+
+pub fn get_platforms() -> &'static [Platform] {
+    PLATFORMS
+}
+
+pub fn find_platform(id: &str) -> Option<&'static Platform> {
+    PLATFORMS.iter().find(|p| p.id.eq_ignore_ascii_case(id))
+}
+
+/// Resolves the ingest url to use for a platform. Catalog servers are always
+/// accepted, anything else has to use the platform's protocol, or rtmps for
+/// platforms that also list secure servers.
+pub fn resolve_ingest_url(platform: &Platform, ingest_url: Option<&str>) -> Result<String, String> {
+    let Some(url) = ingest_url else {
+        return Ok(platform.ingest_servers[0].url.to_string());
+    };
+    let url = url.trim();
+    if platform.ingest_servers.iter().any(|s| s.url == url) {
+        return Ok(url.to_string());
+    }
+    let schemes = platform.schemes();
+    let lower = url.to_ascii_lowercase();
+    if !schemes
+        .iter()
+        .any(|scheme| lower.starts_with(&format!("{}://", scheme)))
+    {
+        return Err(format!(
+            "{} requires {} ingest urls",
+            platform.name,
+            schemes.join(" or ")
+        ));
+    }
+    Ok(url.to_string())
+}
+
+fn video_codec_family(codec: &str) -> &'static str {
+    let codec = codec.to_ascii_lowercase();
+    if codec.contains("264") {
+        "h264"
+    } else if codec.contains("265") || codec.contains("hevc") {
+        "hevc"
+    } else if codec.contains("av1") {
+        "av1"
+    } else if codec.contains("vp9") {
+        "vp9"
+    } else {
+        "unknown"
+    }
+}
+
+fn audio_codec_family(codec: &str) -> &'static str {
+    let codec = codec.to_ascii_lowercase();
+    if codec.contains("aac") {
+        "aac"
+    } else if codec.contains("mp3") {
+        "mp3"
+    } else if codec.contains("opus") {
+        "opus"
+    } else {
+        "unknown"
+    }
+}
+
+/// Compares encoder settings against a platform's published limits. With
+/// passthrough the source is checked instead, once there is one to measure.
+pub fn check_encoder_settings(
+    platform: &'static Platform,
+    settings: &EncoderSettings,
+    source: Option<&SourceStats>,
+) -> Vec<PlatformWarning> {
+    let mut messages = vec![];
+    // passthrough forwards the source untouched, only the streaming software can fix it
+    if settings.use_passthrough {
+        let Some(stats) = source else {
+            return vec![];
+        };
+        if let Some(interval) = stats.keyframe_interval {
+            if interval > platform.keyframe_interval as f64 + KEYFRAME_TOLERANCE_SECS {
+                messages.push(format!(
+                    "The source sends keyframes every {:.1}s, set a {}s keyframe interval in your streaming software",
+                    interval, platform.keyframe_interval
+                ));
+            }
+        }
+        if stats.video_bitrate_kbps > platform.max_video_bitrate as f64 {
+            messages.push(format!(
+                "The source's video bitrate {:.0} kbps is over the {} kbps cap, lower it in your streaming software",
+                stats.video_bitrate_kbps, platform.max_video_bitrate
+            ));
+        }
+        return warnings(platform, messages);
+    }
+    if settings.video_bitrate > platform.max_video_bitrate {
+        messages.push(format!(
+            "Video bitrate {} kbps is over the {} kbps cap",
+            settings.video_bitrate, platform.max_video_bitrate
+        ));
+    }
+    if settings.audio_bitrate > platform.max_audio_bitrate {
+        messages.push(format!(
+            "Audio bitrate {} kbps is over the {} kbps cap",
+            settings.audio_bitrate, platform.max_audio_bitrate
+        ));
+    }
+    let video_codec = video_codec_family(&settings.video_codec);
+    if !platform.video_codecs.contains(&video_codec) {
+        messages.push(format!(
+            "Video codec {} is not supported, use one of {}",
+            settings.video_codec,
+            platform.video_codecs.join(", ")
+        ));
+    }
+    let audio_codec = audio_codec_family(&settings.audio_codec);
+    if !platform.audio_codecs.contains(&audio_codec) {
+        messages.push(format!(
+            "Audio codec {} is not supported, use one of {}",
+            settings.audio_codec,
+            platform.audio_codecs.join(", ")
+        ));
+    }
+    if let Some(framerate) = settings.framerate {
+        if framerate > platform.max_framerate {
+            messages.push(format!(
+                "Framerate {} is over the {} fps limit",
+                framerate, platform.max_framerate
+            ));
+        }
+    }
+    warnings(platform, messages)
+}
+
+fn warnings(platform: &'static Platform, messages: Vec<String>) -> Vec<PlatformWarning> {
+    messages
+        .into_iter()
+        .map(|message| PlatformWarning {
+            target_id: None,
+            platform: platform.name,
+            message,
+        })
+        .collect()
+}
+
+/// Checks every target that was added from the catalog.
+pub fn check_relay_targets(
+    targets: &[RelayTarget],
+    settings: &EncoderSettings,
+    source: Option<&SourceStats>,
+) -> Vec<PlatformWarning> {
+    targets
+        .iter()
+        .filter_map(|target| {
+            let platform = find_platform(target.platform.as_deref()?)?;
+            Some((target.id, platform))
+        })
+        .flat_map(|(id, platform)| {
+            check_encoder_settings(platform, settings, source)
+                .into_iter()
+                .map(move |warning| PlatformWarning {
+                    target_id: Some(id),
+                    ..warning
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, rtmp::KEYFRAME_INTERVAL_SECS};
+
+    fn twitch() -> &'static Platform {
+        find_platform("twitch").unwrap()
+    }
+
+    fn passthrough() -> EncoderSettings {
+        EncoderSettings {
+            use_passthrough: true,
+            ..db::default_encoder_settings()
+        }
+    }
+
+    #[test]
+    fn passthrough_waits_for_a_source() {
+        assert!(check_encoder_settings(twitch(), &passthrough(), None).is_empty());
+    }
+
+    #[test]
+    fn passthrough_accepts_a_source_within_limits() {
+        let stats = SourceStats {
+            keyframe_interval: Some(2.03),
+            video_bitrate_kbps: 5800.0,
+            ..Default::default()
+        };
+        assert!(check_encoder_settings(twitch(), &passthrough(), Some(&stats)).is_empty());
+    }
+
+    #[test]
+    fn passthrough_warns_about_the_measured_source() {
+        let stats = SourceStats {
+            keyframe_interval: Some(5.0),
+            video_bitrate_kbps: 8000.0,
+            ..Default::default()
+        };
+        let warnings = check_encoder_settings(twitch(), &passthrough(), Some(&stats));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].message.contains("every 5.0s"));
+        assert!(warnings[1].message.contains("8000 kbps"));
+    }
+
+    #[test]
+    fn reencoding_checks_the_settings_not_the_source() {
+        let settings = EncoderSettings {
+            use_passthrough: false,
+            video_bitrate: 9000,
+            ..db::default_encoder_settings()
+        };
+        let stats = SourceStats {
+            keyframe_interval: Some(10.0),
+            ..Default::default()
+        };
+        let warnings = check_encoder_settings(twitch(), &settings, Some(&stats));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("9000 kbps"));
+    }
+
+    #[test]
+    fn reencoded_keyframes_suit_every_platform() {
+        for platform in PLATFORMS {
+            assert!(
+                KEYFRAME_INTERVAL_SECS <= platform.keyframe_interval,
+                "{}",
+                platform.id
+            );
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use sqlx::SqlitePool;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+};
+
+use crate::{config, db};
+
+/// Replaces every known stream key in a piece of text with its masked form.
+#[derive(Default)]
+pub struct Redactor {
+    keys: RwLock<Vec<String>>,
+}
+
+impl std::fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let count = self.keys.read().map(|k| k.len()).unwrap_or_default();
+        f.debug_struct("Redactor").field("keys", &count).finish()
+    }
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_keys(&self, keys: Vec<String>) {
+        let mut keys: Vec<String> = keys.into_iter().filter(|k| !k.is_empty()).collect();
+        keys.sort();
+        keys.dedup();
+        // longest first so a key that contains another key is masked whole
+        keys.sort_by_key(|k| std::cmp::Reverse(k.len()));
+        *self.keys.write().unwrap() = keys;
+    }
+
+    pub fn add_key(&self, key: &str) {
+        if key.is_empty() {
+            return;
+        }
+        let mut keys = self.keys.read().unwrap().clone();
+        if !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+            self.set_keys(keys);
+        }
+    }
+
+    pub async fn reload(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let targets = db::get_relay_targets(pool).await?;
+        self.set_keys(targets.into_iter().map(|t| t.stream_key).collect());
+        Ok(())
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let keys = self.keys.read().unwrap();
+        let mut output = text.to_string();
+        for key in keys.iter() {
+            if output.contains(key.as_str()) {
+                output = output.replace(key.as_str(), &config::mask_key(key));
+            }
+        }
+        output
+    }
+}
+
+/// Cuts process output into whole lines, however the reads split it, so a key
+/// is never redacted in halves. ffmpeg ends its progress lines with `\r`, so
+/// those count as line breaks too.
+#[derive(Debug, Default)]
+struct LineSplitter {
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// The next complete line, including its line break.
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        let end = self
+            .pending
+            .iter()
+            .position(|b| *b == b'\n' || *b == b'\r')?;
+        Some(self.pending.drain(..=end).collect())
+    }
+
+    /// Whatever is left once the stream has ended.
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
+/// Copies a child process stream (usually ffmpeg stderr) into a log file,
+/// masking stream keys on the way through.
+pub fn pipe_to_log<R>(reader: R, path: PathBuf, redactor: Arc<Redactor>) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    pipe_to_log_with(reader, path, redactor, |_| {})
+}
+
+/// Like `pipe_to_log`, also handing every redacted line to `on_line`.
+pub fn pipe_to_log_with<R, F>(
+    mut reader: R,
+    path: PathBuf,
+    redactor: Arc<Redactor>,
+    mut on_line: F,
+) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    F: FnMut(&str) + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut file = match File::create(&path).await {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("⚠️ Failed to create log file {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let mut buffer = [0u8; 4096];
+        let mut lines = LineSplitter::default();
+        loop {
+            let n = match reader.read(&mut buffer).await {
+                Ok(n) => n,
+                Err(e) => {
+                    eprintln!("⚠️ Failed to read process output: {}", e);
+                    break;
+                }
+            };
+            if n == 0 {
+                // whatever is left had no line break
+                if let Some(rest) = lines.finish() {
+                    let redacted = redactor.redact(&String::from_utf8_lossy(&rest));
+                    on_line(redacted.trim_end());
+                    let _ = file.write_all(redacted.as_bytes()).await;
+                }
+                break;
+            }
+            lines.push(&buffer[..n]);
+            while let Some(line) = lines.next_line() {
+                let redacted = redactor.redact(&String::from_utf8_lossy(&line));
+                on_line(redacted.trim_end());
+                if file.write_all(redacted.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = file.flush().await;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact_lines(redactor: &Redactor, lines: &mut LineSplitter) -> Vec<String> {
+        std::iter::from_fn(|| lines.next_line())
+            .map(|line| redactor.redact(&String::from_utf8_lossy(&line)))
+            .collect()
+    }
+
+    #[test]
+    fn redacts_a_key_split_across_reads() {
+        let redactor = Redactor::new();
+        redactor.set_keys(vec!["live_1234567890".into()]);
+        let mut lines = LineSplitter::default();
+
+        lines.push(b"Opening rtmp://a.rtmp.youtube.com/live2/live_12345");
+        assert!(redact_lines(&redactor, &mut lines).is_empty());
+        lines.push(b"67890 for writing\n");
+        let redacted = redact_lines(&redactor, &mut lines);
+
+        assert_eq!(
+            redacted,
+            vec!["Opening rtmp://a.rtmp.youtube.com/live2/***********7890 for writing\n"]
+        );
+    }
+
+    #[test]
+    fn splits_progress_lines_on_carriage_returns() {
+        let mut lines = LineSplitter::default();
+        lines.push(b"frame=1 speed=1.0x\rframe=2 speed=0.9x\rframe=3");
+
+        assert_eq!(
+            lines.next_line().as_deref(),
+            Some(&b"frame=1 speed=1.0x\r"[..])
+        );
+        assert_eq!(
+            lines.next_line().as_deref(),
+            Some(&b"frame=2 speed=0.9x\r"[..])
+        );
+        assert_eq!(lines.next_line(), None);
+        assert_eq!(lines.finish().as_deref(), Some(&b"frame=3"[..]));
+        assert_eq!(lines.finish(), None);
+    }
+}
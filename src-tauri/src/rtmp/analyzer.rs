@@ -0,0 +1,183 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+/// Most platforms reject or degrade streams with keyframes further apart than this
+const MAX_KEYFRAME_INTERVAL_SECS: f64 = 4.0;
+/// A jump between consecutive timestamps larger than this counts as a gap
+const TIMESTAMP_GAP_MS: u32 = 1000;
+const MAX_AV_DRIFT_MS: i64 = 500;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceStats {
+    pub bitrate_kbps: f64,
+    pub video_bitrate_kbps: f64,
+    pub audio_bitrate_kbps: f64,
+    pub fps: f64,
+    /// Seconds between the last two keyframes
+    pub keyframe_interval: Option<f64>,
+    /// Latest video timestamp minus latest audio timestamp, in ms
+    pub av_drift_ms: Option<i64>,
+    pub timestamp_gaps: u32,
+    pub total_bytes: u64,
+    pub uptime_secs: u64,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Warning {
+    KeyframeInterval,
+    AvDrift,
+}
+
+/// Tracks the incoming RTMP media so we can tell whether the source is healthy.
+pub struct IngestAnalyzer {
+    started_at: Instant,
+    window_start: Instant,
+    window_video_bytes: u64,
+    window_audio_bytes: u64,
+    window_frames: u32,
+    total_bytes: u64,
+    last_video_ts: Option<u32>,
+    last_audio_ts: Option<u32>,
+    last_keyframe_ts: Option<u32>,
+    keyframe_interval_ms: Option<u32>,
+    timestamp_gaps: u32,
+    warned: Vec<Warning>,
+}
+
+impl Default for IngestAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IngestAnalyzer {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started_at: now,
+            window_start: now,
+            window_video_bytes: 0,
+            window_audio_bytes: 0,
+            window_frames: 0,
+            total_bytes: 0,
+            last_video_ts: None,
+            last_audio_ts: None,
+            last_keyframe_ts: None,
+            keyframe_interval_ms: None,
+            timestamp_gaps: 0,
+            warned: vec![],
+        }
+    }
+
+    fn check_gap(previous: Option<u32>, timestamp: u32) -> bool {
+        match previous {
+            Some(previous) => timestamp < previous || timestamp - previous > TIMESTAMP_GAP_MS,
+            None => false,
+        }
+    }
+
+    /// `data` is the FLV video tag body: the first byte holds frame type and codec id,
+    /// and for AVC the second byte is the packet type (0 = sequence header).
+    pub fn on_video(&mut self, timestamp: u32, data: &[u8]) {
+        self.total_bytes += data.len() as u64;
+        self.window_video_bytes += data.len() as u64;
+        let Some(&flags) = data.first() else {
+            return;
+        };
+        let is_sequence_header = (flags & 0x0F) == 7 && data.get(1) == Some(&0);
+        if is_sequence_header {
+            return;
+        }
+        if Self::check_gap(self.last_video_ts, timestamp) {
+            self.timestamp_gaps += 1;
+        }
+        self.last_video_ts = Some(timestamp);
+        self.window_frames += 1;
+
+        let is_keyframe = (flags >> 4) == 1;
+        if is_keyframe {
+            if let Some(previous) = self.last_keyframe_ts {
+                if timestamp > previous {
+                    self.keyframe_interval_ms = Some(timestamp - previous);
+                }
+            }
+            self.last_keyframe_ts = Some(timestamp);
+        }
+    }
+
+    pub fn on_audio(&mut self, timestamp: u32, data: &[u8]) {
+        self.total_bytes += data.len() as u64;
+        self.window_audio_bytes += data.len() as u64;
+        if Self::check_gap(self.last_audio_ts, timestamp) {
+            self.timestamp_gaps += 1;
+        }
+        self.last_audio_ts = Some(timestamp);
+    }
+
+    fn warnings(stats: &SourceStats) -> Vec<(Warning, String)> {
+        let mut warnings = vec![];
+        if let Some(interval) = stats.keyframe_interval {
+            if interval > MAX_KEYFRAME_INTERVAL_SECS {
+                warnings.push((
+                    Warning::KeyframeInterval,
+                    format!(
+                        "Keyframe interval is {:.1}s, platforms expect {}s or less",
+                        interval, MAX_KEYFRAME_INTERVAL_SECS
+                    ),
+                ));
+            }
+        }
+        if let Some(drift) = stats.av_drift_ms {
+            if drift.abs() > MAX_AV_DRIFT_MS {
+                warnings.push((
+                    Warning::AvDrift,
+                    format!("Audio and video are {}ms apart", drift),
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Returns a fresh snapshot at most once per second, along with any warnings
+    /// that were not raised in the previous snapshot.
+    pub fn tick(&mut self) -> Option<(SourceStats, Vec<String>)> {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < SNAPSHOT_INTERVAL {
+            return None;
+        }
+        let secs = elapsed.as_secs_f64();
+        let video_kbps = self.window_video_bytes as f64 * 8.0 / secs / 1000.0;
+        let audio_kbps = self.window_audio_bytes as f64 * 8.0 / secs / 1000.0;
+        let mut stats = SourceStats {
+            bitrate_kbps: video_kbps + audio_kbps,
+            video_bitrate_kbps: video_kbps,
+            audio_bitrate_kbps: audio_kbps,
+            fps: self.window_frames as f64 / secs,
+            keyframe_interval: self.keyframe_interval_ms.map(|ms| ms as f64 / 1000.0),
+            av_drift_ms: match (self.last_video_ts, self.last_audio_ts) {
+                (Some(video), Some(audio)) => Some(video as i64 - audio as i64),
+                _ => None,
+            },
+            timestamp_gaps: self.timestamp_gaps,
+            total_bytes: self.total_bytes,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            warnings: vec![],
+        };
+        let warnings = Self::warnings(&stats);
+        let new_warnings = warnings
+            .iter()
+            .filter(|(kind, _)| !self.warned.contains(kind))
+            .map(|(_, message)| message.clone())
+            .collect();
+        self.warned = warnings.iter().map(|(kind, _)| *kind).collect();
+        stats.warnings = warnings.into_iter().map(|(_, message)| message).collect();
+
+        self.window_start = Instant::now();
+        self.window_video_bytes = 0;
+        self.window_audio_bytes = 0;
+        self.window_frames = 0;
+        Some((stats, new_warnings))
+    }
+}
@@ -1,72 +1,472 @@
-use super::{fanout::start_fanout, utils::flv_header};
+use super::{fanout::start_fanout, meter, preview, thumbnail, utils::flv_header};
 
 use crate::config::{self};
-use crate::events::AppEvents;
-use std::{fs, process::Stdio, sync::Arc};
-use tauri::{AppHandle, Emitter, Manager};
+use crate::context::AppContext;
+use crate::db::{
+    ChannelMap, CropMode, EncoderSettings, OutputTransform, OverlayPosition, OverlaySettings,
+    OverlayTextMode, PreviewMode,
+};
+use crate::events::{AppEvents, EventPublisher};
+use crate::llhls;
+use crate::metrics;
+use crate::redact;
+use std::{fs, path::Path, process::Stdio, time::Duration};
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 
+/// Seconds between keyframes in everything the encoder produces, what the
+/// catalog platforms ask for.
+pub const KEYFRAME_INTERVAL_SECS: u32 = 2;
+
+pub fn video_encode_args(settings: &EncoderSettings) -> Vec<String> {
+    let mut args = vec![
+        "-c:v".to_string(),
+        settings.video_codec.clone(),
+        "-b:v".to_string(),
+        format!("{}k", settings.video_bitrate),
+        "-bufsize".to_string(),
+        format!("{}k", settings.bufsize.unwrap_or(8000)),
+        "-preset".to_string(),
+        settings.preset.clone(),
+    ];
+    if let Some(tune) = &settings.tune {
+        args.extend(["-tune".to_string(), tune.clone()]);
+    }
+    match settings.framerate.filter(|fps| *fps > 0) {
+        Some(fps) => {
+            let gop = (fps * KEYFRAME_INTERVAL_SECS).to_string();
+            args.extend([
+                "-r".to_string(),
+                fps.to_string(),
+                "-g".to_string(),
+                gop.clone(),
+                "-keyint_min".to_string(),
+                gop,
+            ]);
+        }
+        // the source framerate isn't known up front, so place keyframes by time
+        None => args.extend([
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{})", KEYFRAME_INTERVAL_SECS),
+        ]),
+    }
+    args
+}
+
+fn parse_aspect(aspect: &str) -> Option<(u32, u32)> {
+    let (w, h) = aspect.split_once(':')?;
+    let (w, h) = (w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+/// Builds the `-vf` chain for a per-target transform: crop, then rotate, then scale/pad.
+pub fn transform_filter(transform: &OutputTransform) -> Result<String, String> {
+    let mut filters = vec![];
+    match transform.crop_mode {
+        CropMode::None => {}
+        CropMode::Center => {
+            let aspect = transform.crop_aspect.as_deref().unwrap_or_default();
+            let (w, h) = parse_aspect(aspect)
+                .ok_or_else(|| format!("Invalid crop aspect '{}', expected e.g. 9:16", aspect))?;
+            filters.push(format!(
+                "crop=w='min(iw,ih*{w}/{h})':h='min(ih,iw*{h}/{w})'"
+            ));
+        }
+        CropMode::Rect => match (
+            transform.crop_x,
+            transform.crop_y,
+            transform.crop_width,
+            transform.crop_height,
+        ) {
+            (Some(x), Some(y), Some(w), Some(h)) if w > 0 && h > 0 => {
+                filters.push(format!("crop={w}:{h}:{x}:{y}"));
+            }
+            _ => return Err("Rect crop needs x, y, width and height".into()),
+        },
+    }
+    match transform.rotate {
+        0 => {}
+        90 => filters.push("transpose=clock".into()),
+        180 => filters.push("hflip,vflip".into()),
+        270 => filters.push("transpose=cclock".into()),
+        other => {
+            return Err(format!(
+                "Unsupported rotation {}, use 0, 90, 180 or 270",
+                other
+            ))
+        }
+    }
+    match (transform.output_width, transform.output_height) {
+        (None, None) if transform.pad => {
+            return Err("Padding needs an output width and height".into());
+        }
+        (None, None) => {}
+        (Some(w), Some(h)) if transform.pad => {
+            filters.push(format!(
+                "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2"
+            ));
+        }
+        (w, h) if transform.pad => {
+            return Err(format!(
+                "Padding needs both output width and height, got {:?}x{:?}",
+                w, h
+            ));
+        }
+        // -2 keeps the aspect ratio and an even size when only one side is set
+        (w, h) => filters.push(format!(
+            "scale={}:{}",
+            w.map_or("-2".to_string(), |w| w.to_string()),
+            h.map_or("-2".to_string(), |h| h.to_string())
+        )),
+    }
+    if filters.is_empty() {
+        return Err("Transform does not change the picture".into());
+    }
+    filters.push("setsar=1".into());
+    Ok(filters.join(","))
+}
+
+/// Escapes a value for an ffmpeg filtergraph: once for the filter option parser
+/// and once more for the graph parser.
+pub(super) fn escape_filter_value(value: &str) -> String {
+    let mut option = String::new();
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            option.push('\\');
+        }
+        option.push(c);
+    }
+    let mut graph = String::new();
+    for c in option.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph.push('\\');
+        }
+        graph.push(c);
+    }
+    graph
+}
+
+const OVERLAY_MARGIN: u32 = 20;
+
+/// x/y expressions for placing an item of size (`w`, `h`) inside (`main_w`, `main_h`).
+fn overlay_xy(position: OverlayPosition, main_w: &str, main_h: &str, w: &str, h: &str) -> String {
+    let m = OVERLAY_MARGIN;
+    match position {
+        OverlayPosition::TopLeft => format!("x={m}:y={m}"),
+        OverlayPosition::TopRight => format!("x={main_w}-{w}-{m}:y={m}"),
+        OverlayPosition::BottomLeft => format!("x={m}:y={main_h}-{h}-{m}"),
+        OverlayPosition::BottomRight => format!("x={main_w}-{w}-{m}:y={main_h}-{h}-{m}"),
+        OverlayPosition::Center => format!("x=({main_w}-{w})/2:y=({main_h}-{h})/2"),
+    }
+}
+
+pub fn validate_overlay_settings(overlays: &OverlaySettings) -> Result<(), String> {
+    if overlays.watermark_enabled {
+        let path = overlays.watermark_path.as_deref().unwrap_or_default();
+        if !Path::new(path).is_file() {
+            return Err(format!("Watermark image '{}' does not exist", path));
+        }
+    }
+    if !(0.0..=1.0).contains(&overlays.watermark_opacity) {
+        return Err("Watermark opacity must be between 0 and 1".into());
+    }
+    if overlays.text_size == 0 {
+        return Err("Text size must be greater than 0".into());
+    }
+    let valid_color = !overlays.text_color.is_empty()
+        && overlays
+            .text_color
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '#' | '@' | '.'));
+    if !valid_color {
+        return Err(format!("Invalid text color '{}'", overlays.text_color));
+    }
+    if let Some(font_file) = &overlays.font_file {
+        if !Path::new(font_file).is_file() {
+            return Err(format!("Font file '{}' does not exist", font_file));
+        }
+    }
+    Ok(())
+}
+
+/// Extra inputs and the `-filter_complex` graph for the configured overlays.
+/// The graph reads `[0:v]` and always ends in `[vout]`.
+fn overlay_filter(overlays: &OverlaySettings, text_path: &Path) -> Option<(Vec<String>, String)> {
+    let watermark = overlays
+        .watermark_path
+        .as_deref()
+        .filter(|_| overlays.watermark_enabled);
+    if watermark.is_none() && !overlays.text_enabled {
+        return None;
+    }
+    let mut inputs = vec![];
+    let mut chains = vec![];
+    let mut last = "0:v".to_string();
+    if let Some(path) = watermark {
+        inputs.extend(["-i".to_string(), path.to_string()]);
+        chains.push(format!(
+            "[1:v]format=rgba,colorchannelmixer=aa={}[wm]",
+            overlays.watermark_opacity
+        ));
+        chains.push(format!(
+            "[{last}][wm]overlay={}[wmout]",
+            overlay_xy(overlays.watermark_position, "W", "H", "w", "h")
+        ));
+        last = "wmout".into();
+    }
+    if overlays.text_enabled {
+        let text = match overlays.text_mode {
+            OverlayTextMode::Static => format!(
+                "textfile={}:reload=1",
+                escape_filter_value(&text_path.to_string_lossy())
+            ),
+            OverlayTextMode::Clock => {
+                format!("text={}", escape_filter_value("%{localtime:%T}"))
+            }
+        };
+        let font = overlays
+            .font_file
+            .as_deref()
+            .map(|f| format!(":fontfile={}", escape_filter_value(f)))
+            .unwrap_or_default();
+        chains.push(format!(
+            "[{last}]drawtext={text}{font}:fontsize={}:fontcolor={}:box=1:boxcolor=black@0.5:boxborderw=10:{}[txtout]",
+            overlays.text_size,
+            overlays.text_color,
+            overlay_xy(overlays.text_position, "w", "h", "tw", "th")
+        ));
+        last = "txtout".into();
+    }
+    chains.push(format!("[{last}]null[vout]"));
+    Some((inputs, chains.join(";")))
+}
+
+pub fn validate_encoder_settings(settings: &EncoderSettings) -> Result<(), String> {
+    if let Some(target) = settings.loudness_target {
+        if !(-70.0..=-5.0).contains(&target) {
+            return Err("Loudness target must be between -70 and -5 LUFS".into());
+        }
+    }
+    if let Some(gain) = settings.audio_gain {
+        if !(-30.0..=30.0).contains(&gain) {
+            return Err("Audio gain must be between -30 and 30 dB".into());
+        }
+    }
+    if let Some(channels) = settings.audio_channels {
+        if !(1..=2).contains(&channels) {
+            return Err("Audio channels must be 1 (mono) or 2 (stereo)".into());
+        }
+    }
+    if let Some(sample_rate) = settings.audio_sample_rate {
+        if ![22050, 32000, 44100, 48000].contains(&sample_rate) {
+            return Err(format!("Unsupported sample rate {}", sample_rate));
+        }
+    }
+    if settings.audio_channel_map != ChannelMap::None && settings.audio_channels == Some(1) {
+        return Err("Channel mapping needs stereo output".into());
+    }
+    if settings.dvr_window_mins > 240 {
+        return Err("DVR window must be at most 240 minutes".into());
+    }
+    if settings.dvr_window_mins > 0 && settings.dvr_max_size_mb < 100 {
+        return Err("DVR size cap must be at least 100 MB".into());
+    }
+    if settings.thumbnail_interval_secs > 3600 {
+        return Err("Thumbnail interval must be at most 3600 seconds".into());
+    }
+    Ok(())
+}
+
+/// Audio chain from `[0:a]` to `[aout]`: channel map, gain, loudness, limiter,
+/// then sample rate and channel layout.
+fn audio_filter(settings: &EncoderSettings) -> Option<String> {
+    let mut filters = vec![];
+    match settings.audio_channel_map {
+        ChannelMap::None => {}
+        ChannelMap::LeftToBoth => filters.push("pan=stereo|c0=c0|c1=c0".to_string()),
+        ChannelMap::RightToBoth => filters.push("pan=stereo|c0=c1|c1=c1".to_string()),
+    }
+    if let Some(gain) = settings.audio_gain {
+        filters.push(format!("volume={}dB", gain));
+    }
+    if let Some(target) = settings.loudness_target {
+        filters.push(format!("loudnorm=I={}:TP=-1.5:LRA=11", target));
+    }
+    if settings.audio_limiter {
+        filters.push("alimiter=limit=0.891:level=false".to_string());
+    }
+    // loudnorm upsamples to 192kHz, so always bring the rate back down after it
+    let sample_rate = settings
+        .audio_sample_rate
+        .or(settings.loudness_target.map(|_| 48000));
+    let mut format = vec![];
+    if let Some(sample_rate) = sample_rate {
+        format.push(format!("sample_rates={}", sample_rate));
+    }
+    match settings.audio_channels {
+        Some(1) => format.push("channel_layouts=mono".to_string()),
+        Some(_) => format.push("channel_layouts=stereo".to_string()),
+        None => {}
+    }
+    if !format.is_empty() {
+        filters.push(format!("aformat={}", format.join(":")));
+    }
+    if filters.is_empty() {
+        return None;
+    }
+    Some(format!("[0:a]{}[aout]", filters.join(",")))
+}
+
+/// ffmpeg args for a rendition that re-encodes the program feed with a transform.
+pub fn rendition_args(settings: &EncoderSettings, filter: &str) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "-f", "flv", "-i", "pipe:0", "-map", "0:v", "-map", "0:a?", "-vf",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    args.push(filter.to_string());
+    args.extend(video_encode_args(settings));
+    args.extend(["-c:a", "copy", "-f", "flv", "pipe:1"].map(String::from));
+    args
+}
+
+/// The tee slave that writes the operator preview.
+fn preview_output(app: &AppContext, mode: PreviewMode, dvr: bool) -> String {
+    match mode {
+        // with the DVR on, old segments are removed by the DVR once they leave its window
+        PreviewMode::Hls if dvr => format!(
+            "[f=hls:hls_time=6:hls_list_size=8]{}",
+            config::hls_playlist_path(app).to_string_lossy()
+        ),
+        PreviewMode::Hls => format!(
+            "[f=hls:hls_time=6:hls_list_size=8:hls_flags=delete_segments]{}",
+            config::hls_playlist_path(app).to_string_lossy()
+        ),
+        // split_by_time lets parts end between keyframes, the packager marks which ones can start playback
+        PreviewMode::LlHls => format!(
+            "[f=hls:hls_time={}:hls_list_size={}:hls_segment_type=fmp4:hls_flags=delete_segments+split_by_time+temp_file]{}",
+            llhls::PART_TARGET_SECS,
+            llhls::PARTS_ON_DISK,
+            config::ll_hls_parts_path(app).to_string_lossy()
+        ),
+    }
+}
+
+fn encoder_args(
+    settings: &EncoderSettings,
+    overlays: &OverlaySettings,
+    text_path: &Path,
+    preview: &str,
+) -> Vec<String> {
+    let mut args: Vec<String> = ["-f", "flv", "-i", "pipe:0"].map(String::from).to_vec();
+    if settings.use_passthrough {
+        args.extend(["-c:v", "copy", "-c:a", "copy"].map(String::from));
+    } else {
+        let mut chains = vec![];
+        let video_map = match overlay_filter(overlays, text_path) {
+            Some((inputs, graph)) => {
+                args.extend(inputs);
+                chains.push(graph);
+                "[vout]"
+            }
+            None => "0:v",
+        };
+        let audio_map = match audio_filter(settings) {
+            Some(graph) => {
+                chains.push(graph);
+                "[aout]"
+            }
+            None => "0:a",
+        };
+        if !chains.is_empty() {
+            args.extend(["-filter_complex".into(), chains.join(";")]);
+        }
+        args.extend(["-map", video_map, "-map", audio_map].map(String::from));
+        args.extend(video_encode_args(settings));
+        args.extend([
+            "-c:a".to_string(),
+            settings.audio_codec.clone(),
+            "-b:a".to_string(),
+            format!("{}k", settings.audio_bitrate),
+        ]);
+    }
+    let output = format!("{}|[f=flv]pipe:1", preview);
+    args.extend(["-f".into(), "tee".into(), output]);
+    args
+}
+
 pub async fn start_encoder(
     // initial_data: Vec<u8>,
-    app: &AppHandle,
+    app: &AppContext,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let log_dir = config::log_output_dir(app);
-    let log_file = std::fs::File::create(&log_dir.join("ffmpeg_encoder.log"))?;
-    let log_file = Stdio::from(log_file);
+    let log_path = config::log_output_dir(app).join("ffmpeg_encoder.log");
     let out_dir = config::hls_output_dir(app);
-    let out_path = config::hls_playlist_path(app);
-    fs::create_dir_all(out_dir)?;
-    let state = app.state::<Arc<config::AppState>>();
+    fs::create_dir_all(&out_dir)?;
+    let state = app.state();
     let settings = state.encoder_settings.lock().await.clone();
-    let mut args = vec!["-f", "flv", "-i", "pipe:0"];
-    let video_bitrate = format!("{}k", settings.video_bitrate);
-    let audio_bitrate = format!("{}k", settings.audio_bitrate);
-    let bufsize = format!("{}k", settings.bufsize.unwrap_or(8000));
-    if settings.use_passthrough {
-        args.extend(["-c:v", "copy", "-c:a", "copy"]);
-    } else {
-        args.extend([
-            "-map",
-            "0:v",
-            "-map",
-            "0:a",
-            "-c:v",
-            &settings.video_codec,
-            "-b:v",
-            &video_bitrate,
-            "-bufsize",
-            &bufsize,
-            "-preset",
-            &settings.preset,
-            "-c:a",
-            &settings.audio_codec,
-            "-b:a",
-            &audio_bitrate,
-        ]);
-        if let Some(tune) = &settings.tune {
-            args.extend(["-tune", tune]);
-        }
+    let ll_hls_dir = config::ll_hls_dir(app);
+    if settings.preview_mode == PreviewMode::LlHls {
+        fs::create_dir_all(&ll_hls_dir)?;
+    }
+    let overlays = state.overlay_settings.lock().await.clone();
+    let text_path = config::overlay_text_path(app);
+    if overlays.text_enabled && !text_path.exists() {
+        config::write_overlay_text(app, &overlays.text_content)?;
+    }
+    if settings.use_passthrough && (overlays.watermark_enabled || overlays.text_enabled) {
+        eprintln!("⚠️ Overlays are ignored while passthrough is enabled");
+    }
+    if settings.use_passthrough && audio_filter(&settings).is_some() {
+        eprintln!("⚠️ Audio processing is ignored while passthrough is enabled");
+    }
+    if settings.dvr_window_mins > 0 && settings.preview_mode == PreviewMode::LlHls {
+        eprintln!("⚠️ The DVR only records the HLS preview, it is off in LL-HLS mode");
     }
-    let output = format!(
-        "[f=hls:hls_time=6:hls_list_size=8:hls_flags=delete_segments]{}|[f=flv]pipe:1",
-        out_path.to_string_lossy()
-    );
-    args.extend(["-f", "tee", output.as_str()]);
+    let dvr = settings.dvr_window_mins > 0 && settings.preview_mode == PreviewMode::Hls;
+    let preview = preview_output(app, settings.preview_mode, dvr);
+    let args = encoder_args(&settings, &overlays, &text_path, &preview);
     let mut ffmpeg = Command::new("ffmpeg")
         .args(args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(log_file)
+        .stderr(Stdio::piped())
         .spawn()?;
 
+    let stderr = ffmpeg.stderr.take().unwrap();
+    let speed_app = app.clone();
+    redact::pipe_to_log_with(stderr, log_path, state.redactor.clone(), move |line| {
+        if let Some(speed) = metrics::parse_speed(line) {
+            speed_app.state().metrics.set_encoder_speed(Some(speed));
+        }
+    });
     let mut stdin = ffmpeg.stdin.take().unwrap();
     let stdout = ffmpeg.stdout.take().unwrap();
 
     if stdin.write_all(&flv_header()).await.is_ok() {
         *state.encoder_stdin.lock().await = Some(stdin);
     }
+    let pid = ffmpeg.id();
     *state.encoder_process.lock().await = Some(ffmpeg);
+    monitor_encoder(app.clone(), pid);
+    if settings.preview_mode == PreviewMode::LlHls {
+        state.ll_hls.start(ll_hls_dir).await;
+    }
+    if dvr {
+        state
+            .dvr
+            .start(out_dir, settings.dvr_window_mins, settings.dvr_max_size_mb)
+            .await;
+    }
+
+    // the meter and preview have to be subscribed before the first bytes reach the fanout
+    if let Err(e) = meter::start_meter(app).await {
+        eprintln!("⚠️ Failed to start audio meter: {}", e);
+    }
+    preview::start_preview(app).await;
+    if let Err(e) = thumbnail::start_thumbnailer(app).await {
+        eprintln!("⚠️ Failed to start thumbnails: {}", e);
+    }
 
     // possibly store fanout task
     let app_clone = app.clone();
@@ -78,8 +478,49 @@ pub async fn start_encoder(
     Ok(())
 }
 
-pub async fn stop_encoder(app: &AppHandle) {
-    let state = app.state::<Arc<config::AppState>>();
+/// Reports the encoder exiting on its own. stop_encoder takes the process out
+/// before it exits, which ends the monitor quietly.
+fn monitor_encoder(app: AppContext, pid: Option<u32>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let state = app.state();
+            let mut process = state.encoder_process.lock().await;
+            // a restarted encoder has its own monitor
+            let Some(child) = process.as_mut().filter(|child| child.id() == pid) else {
+                return;
+            };
+            match child.try_wait() {
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    eprintln!(
+                        "💥 Encoder exited unexpectedly with code {:?}",
+                        status.code()
+                    );
+                    *process = None;
+                    app.publish(
+                        AppEvents::EncoderCrashed,
+                        format!("Exited with code {:?}", status.code()),
+                    );
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("⚠️ Failed to poll encoder process: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+pub async fn stop_encoder(app: &AppContext) {
+    meter::stop_meter(app).await;
+    thumbnail::stop_thumbnailer(app).await;
+    preview::stop_preview(app).await;
+    let state = app.state();
+    state.ll_hls.stop().await;
+    state.dvr.stop().await;
+    state.metrics.set_encoder_speed(None);
     let mut process_guard = state.encoder_process.lock().await;
     *state.encoder_stdin.lock().await = None;
 
@@ -94,10 +535,7 @@ pub async fn stop_encoder(app: &AppHandle) {
         }
     }
 
-    app.emit(AppEvents::StreamPreviewEnded.as_str(), ())
-        .unwrap_or_else(|_| {
-            eprintln!("⚠️ Failed to emit stream preview stopped event");
-        });
+    app.publish(AppEvents::StreamPreviewEnded, ());
     let out_dir = config::hls_output_dir(&app);
     if out_dir.exists() {
         fs::remove_dir_all(out_dir).unwrap_or_else(|_| {
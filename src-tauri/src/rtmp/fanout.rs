@@ -1,16 +1,115 @@
-use crate::config::AppState;
-use std::sync::Arc;
-use tauri::{AppHandle, Manager};
-use tokio::io::AsyncReadExt;
+use crate::{config::RenditionHandle, context::AppContext};
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    io::AsyncReadExt,
+    sync::{mpsc, Mutex},
+};
 
 use super::utils::{is_audio_aac_sequence_header, is_video_keyframe_avc_sequence_header};
 
-pub async fn start_fanout(
-    app: AppHandle,
+/// FLV tag header plus the PreviousTagSize that follows the data
+const TAG_OVERHEAD: usize = 11 + 4;
+
+/// Whether `data` starts with an FLV tag. None means it might, but more bytes are needed to tell.
+fn is_tag_start(data: &[u8]) -> Option<bool> {
+    if data.len() < 11 {
+        return None;
+    }
+    let valid_header = matches!(data[0] & 0x1F, 8 | 9 | 18) && data[8..11] == [0, 0, 0];
+    if !valid_header {
+        return Some(false);
+    }
+    let size = u32::from_be_bytes([0, data[1], data[2], data[3]]) as usize;
+    let end = size + TAG_OVERHEAD;
+    if data.len() < end {
+        return None;
+    }
+    let previous_size =
+        u32::from_be_bytes([data[end - 4], data[end - 3], data[end - 2], data[end - 1]]);
+    Some(previous_size as usize == size + 11)
+}
+
+/// Splits an encoder's output into whole FLV tags, however the reads cut it.
+/// Should the stream ever be corrupt, it looks for the next tag boundary.
+#[derive(Debug, Default)]
+struct FlvTagReader {
+    buf: Vec<u8>,
+}
+
+impl FlvTagReader {
+    fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(data);
+        let mut tags = vec![];
+        loop {
+            // ffmpeg starts its output with a file header
+            if self.buf.starts_with(b"FLV") {
+                if self.buf.len() < 13 {
+                    break;
+                }
+                self.buf.drain(..13);
+                continue;
+            }
+            match is_tag_start(&self.buf) {
+                Some(true) => {
+                    let size = u32::from_be_bytes([0, self.buf[1], self.buf[2], self.buf[3]]);
+                    let end = size as usize + TAG_OVERHEAD;
+                    tags.push(self.buf.drain(..end).collect());
+                }
+                Some(false) => {
+                    if !self.resync() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        tags
+    }
+
+    /// Drops bytes up to the next plausible tag. Returns false when more data is needed.
+    fn resync(&mut self) -> bool {
+        for offset in 1..self.buf.len() {
+            match is_tag_start(&self.buf[offset..]) {
+                Some(false) => continue,
+                Some(true) => {
+                    self.buf.drain(..offset);
+                    return true;
+                }
+                None => {
+                    self.buf.drain(..offset);
+                    return false;
+                }
+            }
+        }
+        self.buf.clear();
+        false
+    }
+}
+
+pub async fn start_fanout(app: AppContext, stdout: tokio::process::ChildStdout) {
+    let state = app.state();
+    fanout(
+        stdout,
+        &state.encoder_sequence_headers,
+        &state.relay_channels,
+        Some(&state.renditions),
+        Some(&state.feed_taps),
+    )
+    .await;
+}
+
+/// Reads FLV output from an encoder and copies every chunk to the subscribed
+/// relays (and renditions, for the program feed), caching sequence headers.
+/// Taps get whole tags instead, so one that falls behind only ever misses whole tags.
+pub async fn fanout(
     mut stdout: tokio::process::ChildStdout,
+    sequence_headers: &Mutex<Vec<Vec<u8>>>,
+    channels: &Mutex<HashMap<i64, mpsc::Sender<Arc<Vec<u8>>>>>,
+    renditions: Option<&Mutex<HashMap<String, RenditionHandle>>>,
+    taps: Option<&Mutex<HashMap<String, mpsc::Sender<Arc<Vec<u8>>>>>>,
 ) {
-    let state = app.state::<Arc<AppState>>();
     let mut buf = [0u8; 4096];
+    let mut tag_reader = FlvTagReader::default();
 
     loop {
         match stdout.read(&mut buf).await {
@@ -20,7 +119,7 @@ pub async fn start_fanout(
             }
             Ok(n) => {
                 let chunk = Arc::new(buf[..n].to_vec());
-                let mut headers = state.encoder_sequence_headers.lock().await;
+                let mut headers = sequence_headers.lock().await;
                 if is_video_keyframe_avc_sequence_header(&chunk) {
                     println!("✅ Encoder video sequence header received");
                     headers.push(chunk.to_vec());
@@ -29,12 +128,44 @@ pub async fn start_fanout(
                     println!("✅ Encoder audio sequence header received");
                     headers.push(chunk.to_vec());
                 }
-                let relay_channels = state.relay_channels.lock().await;
+                drop(headers);
+                let relay_channels = channels.lock().await;
                 for (id, tx) in relay_channels.iter() {
                     if let Err(e) = tx.send(Arc::clone(&chunk)).await {
                         eprintln!("⚠️ Failed to send to relay {}: {}", id, e);
                     }
                 }
+                drop(relay_channels);
+                if let Some(renditions) = renditions {
+                    // send outside the lock, a backed up rendition must not block attach and detach
+                    let inputs: Vec<_> = renditions
+                        .lock()
+                        .await
+                        .iter()
+                        .map(|(key, rendition)| (key.clone(), rendition.input.clone()))
+                        .collect();
+                    for (key, input) in inputs {
+                        if let Err(e) = input.send(Arc::clone(&chunk)).await {
+                            eprintln!("⚠️ Failed to send to rendition {}: {}", key, e);
+                        }
+                    }
+                }
+                if let Some(taps) = taps {
+                    // taps must never hold up the relays, so drop tags they can't keep up with
+                    let tags = tag_reader.feed(&chunk);
+                    if !tags.is_empty() {
+                        let mut taps = taps.lock().await;
+                        for tag in tags.into_iter().map(Arc::new) {
+                            taps.retain(|name, tx| match tx.try_send(Arc::clone(&tag)) {
+                                Err(mpsc::error::TrySendError::Closed(_)) => {
+                                    println!("🔌 Feed tap {} closed", name);
+                                    false
+                                }
+                                _ => true,
+                            });
+                        }
+                    }
+                }
             }
             Err(e) => {
                 eprintln!("❌ Error reading encoder stdout: {}", e);
@@ -43,3 +174,43 @@ pub async fn start_fanout(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtmp::utils::{flv_header, flv_tag, FlvTagType};
+
+    fn video(timestamp: u32) -> Vec<u8> {
+        let mut data = vec![0x27, 1, 0, 0, 0];
+        data.resize(20, 0xAA);
+        flv_tag(FlvTagType::Video, timestamp, &data)
+    }
+
+    #[test]
+    fn joins_tags_split_across_reads() {
+        let tags = vec![
+            video(0),
+            flv_tag(FlvTagType::Audio, 10, &[0xAF, 1, 0x21]),
+            video(33),
+        ];
+        let stream = [flv_header(), tags.concat()].concat();
+        let mut reader = FlvTagReader::default();
+        let mut found = vec![];
+        for chunk in stream.chunks(5) {
+            found.extend(reader.feed(chunk));
+        }
+        assert_eq!(found, tags);
+    }
+
+    #[test]
+    fn resyncs_after_a_gap() {
+        let tags = [video(0), video(33), video(66)];
+        let mut reader = FlvTagReader::default();
+        // the start of the first tag went missing
+        let mut found = reader.feed(&tags[0][6..]);
+        assert!(found.is_empty());
+        found.extend(reader.feed(&tags[1]));
+        found.extend(reader.feed(&tags[2]));
+        assert_eq!(found, tags[1..]);
+    }
+}
@@ -1,36 +1,153 @@
-use super::session;
+use super::{
+    session::{self, TimeoutStage},
+    source,
+    utils::{read_socket, SocketRead},
+};
 
-use crate::config::{self};
+use crate::{
+    config::{self, ListenerHandle, SourceConnection},
+    context::AppContext,
+    events::{AppEvents, EventPublisher},
+    pipeline,
+};
 use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
-use std::sync::{atomic::Ordering, Arc};
-use tauri::{AppHandle, Manager};
+use serde::Serialize;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::AsyncWriteExt,
     net::{TcpListener, TcpStream},
 };
+use tokio_util::sync::CancellationToken;
 
-pub async fn init_rtmp_server(app: AppHandle, port: u16) {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))
-        .await
-        .expect("Failed to bind");
-    println!("🟢 RTMP server listening on rtmp://localhost:1580");
-    let app_state = app.state::<Arc<crate::config::AppState>>();
-    app_state.rtmp_ready.store(true, Ordering::SeqCst);
+#[derive(Debug, Clone, Serialize)]
+struct IngestRejected {
+    address: String,
+    reason: &'static str,
+    total: u64,
+}
+
+pub async fn init_rtmp_server(app: AppContext, port: u16) {
+    let settings = app.state().ingest_settings.lock().await.clone();
+    let ip = config::ingest_bind_ip(&settings).unwrap_or_else(|e| {
+        eprintln!("⚠️ {}, listening on all interfaces", e);
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    });
+    let Err(e) = start_rtmp_listener(&app, ip, port).await else {
+        return;
+    };
+    eprintln!("⚠️ Failed to bind RTMP port {}: {}", port, e);
+    if config::is_port_available(port).await {
+        eprintln!("❌ RTMP ingest is down");
+        return;
+    }
+    let result = match pipeline::fall_back_port(&app, "rtmp", port).await {
+        Ok(fallback) => start_rtmp_listener(&app, ip, fallback)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("❌ RTMP ingest is down: {}", e);
+    }
+}
+
+/// Binds the ingest listener, replacing the running one. A publisher that is
+/// already connected keeps streaming. If the new address can't be bound the
+/// previous one is restored.
+pub async fn start_rtmp_listener(app: &AppContext, ip: IpAddr, port: u16) -> std::io::Result<()> {
+    let state = app.state();
+    let mut current = state.rtmp_listener.lock().await;
+    let previous_addr = match current.take() {
+        Some(previous) => {
+            previous.task.abort();
+            // the listening socket is only closed once the aborted task is dropped
+            let _ = previous.task.await;
+            Some(previous.addr)
+        }
+        None => None,
+    };
+    let listener = match TcpListener::bind((ip, port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            if let Some(addr) = previous_addr {
+                if let Ok(listener) = TcpListener::bind(addr).await {
+                    eprintln!("⚠️ Keeping RTMP server on {}", addr);
+                    *current = Some(ListenerHandle {
+                        addr,
+                        task: tokio::spawn(accept_connections(app.clone(), listener)),
+                    });
+                }
+            }
+            return Err(e);
+        }
+    };
+    let addr = listener.local_addr()?;
+    println!("🟢 RTMP server listening on rtmp://{}", addr);
+    *current = Some(ListenerHandle {
+        addr,
+        task: tokio::spawn(accept_connections(app.clone(), listener)),
+    });
+    state.rtmp_ready.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+fn reject_connection(app: &AppContext, addr: SocketAddr, reason: &'static str) {
+    let state = app.state();
+    let total = state.rejected_connections.fetch_add(1, Ordering::SeqCst) + 1;
+    eprintln!("🚫 Rejecting RTMP connection from {}: {}", addr, reason);
+    app.publish(
+        AppEvents::IngestRejected,
+        IngestRejected {
+            address: addr.ip().to_string(),
+            reason,
+            total,
+        },
+    );
+}
+
+async fn accept_connections(app: AppContext, listener: TcpListener) {
+    let app_state = app.state();
     loop {
-        let (socket, addr) = listener.accept().await.expect("Failed to accept");
+        let (socket, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("⚠️ Failed to accept RTMP connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
         println!("🔗 Connection from {}", addr);
+        if !app_state.access_list.lock().await.is_allowed(addr.ip()) {
+            reject_connection(&app, addr, "address not allowed");
+            continue;
+        }
+        if app_state.is_banned(addr.ip()).await {
+            reject_connection(&app, addr, "address is banned");
+            continue;
+        }
         if app_state.rtmp_active.swap(true, Ordering::SeqCst) {
-            eprintln!("⚠️ Rejecting RTMP connection from {addr}: stream already in use");
+            reject_connection(&app, addr, "stream already in use");
             drop(socket); // Close the connection
             continue;
         }
         println!("🔗 Accepted RTMP connection from {addr}");
-        let app_clone: AppHandle = app.clone();
+        let cancel = CancellationToken::new();
+        *app_state.source_connection.lock().await = Some(SourceConnection {
+            addr,
+            cancel: cancel.clone(),
+        });
+        let app_clone: AppContext = app.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(app_clone.clone(), socket).await {
+            if let Err(e) = handle_connection(app_clone.clone(), socket, cancel).await {
                 eprintln!("❌ Error: {}", e);
             }
-            let state = app_clone.state::<Arc<config::AppState>>();
+            source::clear_source_info(&app_clone).await;
+            let state = app_clone.state();
+            *state.source_connection.lock().await = None;
             state.rtmp_active.store(false, Ordering::SeqCst);
             println!("📴 RTMP connection ended");
         });
@@ -38,17 +155,38 @@ pub async fn init_rtmp_server(app: AppHandle, port: u16) {
 }
 
 async fn handle_connection(
-    app: AppHandle,
+    app: AppContext,
     mut socket: TcpStream,
+    cancel: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("📡 Handling RTMP connection...");
 
+    let connected_at = Instant::now();
+    let timeout_secs = app
+        .state()
+        .ingest_settings
+        .lock()
+        .await
+        .handshake_timeout_secs;
+    let deadline = session::deadline(connected_at, timeout_secs);
     let mut handshake = Handshake::new(PeerType::Server);
     let mut buffer = [0u8; 4096];
     let mut received_data = Vec::new();
 
     loop {
-        let n = socket.read(&mut buffer).await?;
+        let n = match read_socket(&mut socket, &mut buffer, &cancel, deadline).await {
+            SocketRead::Data(result) => result?,
+            SocketRead::Cancelled => {
+                println!("✂️ Source disconnected during handshake");
+                let _ = socket.shutdown().await;
+                return Ok(());
+            }
+            SocketRead::TimedOut => {
+                session::report_timeout(&app, TimeoutStage::Handshake, timeout_secs);
+                let _ = socket.shutdown().await;
+                return Ok(());
+            }
+        };
         if n == 0 {
             return Err("🔌 Connection closed during handshake".into());
         }
@@ -66,7 +204,14 @@ async fn handle_connection(
             }) => {
                 socket.write_all(&response_bytes).await?;
                 println!("✅ RTMP handshake complete 🤝");
-                return session::handle_session(&app, socket, remaining_bytes.to_vec()).await;
+                return session::handle_session(
+                    &app,
+                    socket,
+                    remaining_bytes.to_vec(),
+                    cancel,
+                    connected_at,
+                )
+                .await;
             }
 
             Err(e) => {
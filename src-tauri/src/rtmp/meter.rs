@@ -0,0 +1,222 @@
+use super::{encoder::escape_filter_value, utils::flv_header};
+
+use crate::{
+    config::{self, MeterHandle},
+    context::AppContext,
+    events::{AppEvents, EventPublisher},
+    redact,
+};
+use serde::Serialize;
+use std::{
+    process::Stdio,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::mpsc,
+};
+
+const METER_TAP: &str = "audio-meter";
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+/// Peaks below this count as silence
+const SILENCE_THRESHOLD_DB: f64 = -60.0;
+const SILENCE_WARN_AFTER: Duration = Duration::from_secs(5);
+/// ffmpeg reports -inf for digital silence, which JSON can't carry
+const METER_FLOOR_DB: f64 = -100.0;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChannelLevel {
+    pub peak: f64,
+    pub rms: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AudioLevels {
+    pub channels: Vec<ChannelLevel>,
+    pub momentary_lufs: Option<f64>,
+    pub short_term_lufs: Option<f64>,
+    pub integrated_lufs: Option<f64>,
+}
+
+impl AudioLevels {
+    fn channel(&mut self, index: usize) -> &mut ChannelLevel {
+        if self.channels.len() < index {
+            self.channels.resize(
+                index,
+                ChannelLevel {
+                    peak: METER_FLOOR_DB,
+                    rms: METER_FLOOR_DB,
+                },
+            );
+        }
+        &mut self.channels[index - 1]
+    }
+
+    fn max_peak(&self) -> f64 {
+        self.channels
+            .iter()
+            .map(|c| c.peak)
+            .fold(METER_FLOOR_DB, f64::max)
+    }
+
+    /// Applies one `key=value` line printed by ametadata.
+    fn apply(&mut self, key: &str, value: f64) {
+        let value = value.max(METER_FLOOR_DB);
+        if let Some(rest) = key.strip_prefix("lavfi.astats.") {
+            let Some((channel, stat)) = rest.split_once('.') else {
+                return;
+            };
+            let Ok(channel) = channel.parse::<usize>() else {
+                return;
+            };
+            if channel == 0 {
+                return;
+            }
+            match stat {
+                "Peak_level" => self.channel(channel).peak = value,
+                "RMS_level" => self.channel(channel).rms = value,
+                _ => {}
+            }
+        } else {
+            match key {
+                "lavfi.r128.M" => self.momentary_lufs = Some(value),
+                "lavfi.r128.S" => self.short_term_lufs = Some(value),
+                "lavfi.r128.I" => self.integrated_lufs = Some(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+struct SilenceState {
+    last_sound: Instant,
+    warned: bool,
+}
+
+fn meter_args() -> Vec<String> {
+    // 4800 samples is 100ms at 48kHz; astats resets each frame so levels are per window
+    let filter = format!(
+        "asetnsamples=n=4800:p=0,astats=metadata=1:reset=1:measure_perchannel=Peak_level+RMS_level:measure_overall=none,ebur128=metadata=1:framelog=verbose,ametadata=mode=print:file={}",
+        escape_filter_value("pipe:1")
+    );
+    [
+        "-f",
+        "flv",
+        "-i",
+        "pipe:0",
+        "-vn",
+        "-map",
+        "0:a?",
+        "-af",
+        filter.as_str(),
+        "-f",
+        "null",
+        "-",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Starts a side ffmpeg that decodes the program audio and reports levels.
+/// It runs separately so a bad or missing audio track can't take down the encoder.
+pub async fn start_meter(app: &AppContext) -> Result<(), Box<dyn std::error::Error>> {
+    let state = app.state();
+    let log_path = config::log_output_dir(app).join("ffmpeg_meter.log");
+    let mut child = Command::new("ffmpeg")
+        .args(meter_args())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take().unwrap();
+    redact::pipe_to_log(stderr, log_path, state.redactor.clone());
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    let (tx, mut rx) = mpsc::channel::<Arc<Vec<u8>>>(1024);
+    state.register_feed_tap(METER_TAP, tx).await;
+    let input_task = tokio::spawn(async move {
+        if stdin.write_all(&flv_header()).await.is_err() {
+            return;
+        }
+        while let Some(data) = rx.recv().await {
+            if stdin.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let silence = Arc::new(StdMutex::new(SilenceState {
+        last_sound: Instant::now(),
+        warned: false,
+    }));
+
+    let app_clone = app.clone();
+    let silence_clone = silence.clone();
+    let parse_task = tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut levels = AudioLevels::default();
+        let mut last_emit = Instant::now();
+        while let Ok(Some(line)) = lines.next_line().await {
+            // every frame starts with "frame:N pts:... pts_time:..."
+            if line.starts_with("frame:") {
+                if levels.channels.is_empty() {
+                    continue;
+                }
+                if levels.max_peak() > SILENCE_THRESHOLD_DB {
+                    let mut silence = silence_clone.lock().unwrap();
+                    silence.last_sound = Instant::now();
+                    if silence.warned {
+                        silence.warned = false;
+                        app_clone.publish(AppEvents::AudioSilenceEnded, ());
+                    }
+                }
+                if last_emit.elapsed() >= EMIT_INTERVAL {
+                    app_clone.publish(AppEvents::AudioLevels, &levels);
+                    last_emit = Instant::now();
+                }
+                levels.channels.clear();
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                if let Ok(value) = value.trim().parse::<f64>() {
+                    levels.apply(key.trim(), value);
+                }
+            }
+        }
+    });
+
+    let app_clone = app.clone();
+    let silence_task = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let mut silence = silence.lock().unwrap();
+            let silent_for = silence.last_sound.elapsed();
+            if silent_for >= SILENCE_WARN_AFTER && !silence.warned {
+                silence.warned = true;
+                eprintln!("🔇 No audio for {}s", silent_for.as_secs());
+                app_clone.publish(AppEvents::AudioSilenceDetected, silent_for.as_secs());
+            }
+        }
+    });
+
+    *state.meter.lock().await = Some(MeterHandle {
+        process: child,
+        tasks: vec![input_task, parse_task, silence_task],
+    });
+    Ok(())
+}
+
+pub async fn stop_meter(app: &AppContext) {
+    let state = app.state();
+    state.unregister_feed_tap(METER_TAP).await;
+    if let Some(mut meter) = state.meter.lock().await.take() {
+        for task in meter.tasks {
+            task.abort();
+        }
+        let _ = meter.process.kill().await;
+    }
+}
@@ -1,9 +1,26 @@
+mod analyzer;
 mod encoder;
 mod handshake;
+mod meter;
+mod preview;
 pub mod relay;
+mod rendition;
 pub mod session;
+mod source;
+mod thumbnail;
 mod utils;
 mod fanout;
 
-pub use encoder::stop_encoder;
+pub use analyzer::SourceStats;
+pub use encoder::{stop_encoder, KEYFRAME_INTERVAL_SECS};
 pub use handshake::init_rtmp_server;
+pub use preview::{subscribe_preview, FlvPreview, PreviewViewer};
+pub use rendition::rendition_id;
+pub use source::SourceInfo;
+
+#[cfg(feature = "desktop")]
+pub use encoder::{transform_filter, validate_encoder_settings, validate_overlay_settings};
+#[cfg(feature = "desktop")]
+pub use handshake::start_rtmp_listener;
+#[cfg(feature = "desktop")]
+pub use thumbnail::capture_snapshot;
@@ -0,0 +1,263 @@
+use super::utils::flv_header;
+
+use crate::{config::PreviewHandle, context::AppContext};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+
+const PREVIEW_TAP: &str = "flv-preview";
+/// A GOP larger than this is not cached, new viewers wait for the next keyframe instead
+const GOP_CACHE_LIMIT: usize = 16 * 1024 * 1024;
+const VIEWER_BACKLOG: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TagKind {
+    Metadata,
+    VideoHeader,
+    AudioHeader,
+    Keyframe,
+    Frame,
+}
+
+fn tag_kind(tag: &[u8]) -> TagKind {
+    match (tag[0] & 0x1F, tag.get(11), tag.get(12)) {
+        (18, _, _) => TagKind::Metadata,
+        // AVC packet type 0 is the sequence header
+        (9, Some(flags), Some(0)) if flags & 0x0F == 7 => TagKind::VideoHeader,
+        (9, Some(flags), _) if flags >> 4 == 1 => TagKind::Keyframe,
+        (8, Some(flags), Some(0)) if flags >> 4 == 10 => TagKind::AudioHeader,
+        _ => TagKind::Frame,
+    }
+}
+
+#[derive(Debug, Default)]
+struct TagCache {
+    metadata: Option<Arc<Vec<u8>>>,
+    video_header: Option<Arc<Vec<u8>>>,
+    audio_header: Option<Arc<Vec<u8>>>,
+    /// Every tag since the last keyframe, so new viewers can start decoding at once
+    gop: Vec<Arc<Vec<u8>>>,
+    gop_bytes: usize,
+}
+
+/// Rebroadcasts the program feed as FLV tags for low-latency HTTP-FLV and WS-FLV preview.
+#[derive(Debug)]
+pub struct FlvPreview {
+    cache: StdMutex<TagCache>,
+    tags: broadcast::Sender<Arc<Vec<u8>>>,
+}
+
+impl FlvPreview {
+    fn new() -> Self {
+        Self {
+            cache: StdMutex::new(TagCache::default()),
+            tags: broadcast::channel(VIEWER_BACKLOG).0,
+        }
+    }
+
+    fn push(&self, tag: Arc<Vec<u8>>) {
+        // publish under the cache lock so a new viewer never misses or repeats a tag
+        let mut cache = self.cache.lock().unwrap();
+        match tag_kind(&tag) {
+            TagKind::Metadata => cache.metadata = Some(tag.clone()),
+            TagKind::VideoHeader => cache.video_header = Some(tag.clone()),
+            TagKind::AudioHeader => cache.audio_header = Some(tag.clone()),
+            TagKind::Keyframe => {
+                cache.gop.clear();
+                cache.gop_bytes = tag.len();
+                cache.gop.push(tag.clone());
+            }
+            TagKind::Frame => {
+                if !cache.gop.is_empty() {
+                    cache.gop_bytes += tag.len();
+                    if cache.gop_bytes > GOP_CACHE_LIMIT {
+                        cache.gop.clear();
+                    } else {
+                        cache.gop.push(tag.clone());
+                    }
+                }
+            }
+        }
+        let _ = self.tags.send(tag);
+    }
+
+    /// Returns the bytes a new viewer starts with (file header, metadata, sequence
+    /// headers and the current GOP) and a viewer for the live tags that follow.
+    pub fn subscribe(&self) -> (Vec<u8>, PreviewViewer) {
+        let cache = self.cache.lock().unwrap();
+        let mut start = flv_header();
+        let cached = cache
+            .metadata
+            .iter()
+            .chain(&cache.video_header)
+            .chain(&cache.audio_header)
+            .chain(&cache.gop);
+        for tag in cached {
+            start.extend_from_slice(tag);
+        }
+        let viewer = PreviewViewer {
+            rx: self.tags.subscribe(),
+            waiting_for_keyframe: cache.gop.is_empty(),
+        };
+        (start, viewer)
+    }
+}
+
+pub struct PreviewViewer {
+    rx: broadcast::Receiver<Arc<Vec<u8>>>,
+    waiting_for_keyframe: bool,
+}
+
+impl PreviewViewer {
+    /// Next tag to send. A viewer that falls behind skips ahead to the next keyframe.
+    pub async fn next(&mut self) -> Option<Arc<Vec<u8>>> {
+        loop {
+            match self.rx.recv().await {
+                Ok(tag) => {
+                    if self.waiting_for_keyframe {
+                        match tag_kind(&tag) {
+                            TagKind::Keyframe => self.waiting_for_keyframe = false,
+                            TagKind::Frame => continue,
+                            _ => {}
+                        }
+                    }
+                    return Some(tag);
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("⚠️ Preview viewer fell behind by {} tags", skipped);
+                    self.waiting_for_keyframe = true;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Taps the program feed for the FLV preview. Like the meter, it has to be
+/// subscribed before the fanout starts so it sees the sequence headers.
+pub async fn start_preview(app: &AppContext) {
+    let state = app.state();
+    let preview = Arc::new(FlvPreview::new());
+    let (tx, mut rx) = mpsc::channel::<Arc<Vec<u8>>>(4096);
+    state.register_feed_tap(PREVIEW_TAP, tx).await;
+    let preview_clone = preview.clone();
+    let task = tokio::spawn(async move {
+        while let Some(tag) = rx.recv().await {
+            preview_clone.push(tag);
+        }
+    });
+    *state.flv_preview.lock().await = Some(PreviewHandle { preview, task });
+}
+
+pub async fn stop_preview(app: &AppContext) {
+    let state = app.state();
+    state.unregister_feed_tap(PREVIEW_TAP).await;
+    // dropping the preview closes the broadcast, which ends every viewer's response
+    if let Some(handle) = state.flv_preview.lock().await.take() {
+        handle.task.abort();
+    }
+}
+
+/// Starts a preview viewer, or None when nothing is being encoded.
+pub async fn subscribe_preview(app: &AppContext) -> Option<(Vec<u8>, PreviewViewer)> {
+    let state = app.state();
+    let guard = state.flv_preview.lock().await;
+    Some(guard.as_ref()?.preview.subscribe())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtmp::utils::{flv_tag, FlvTagType};
+
+    fn video(timestamp: u32, flags: u8, packet_type: u8, len: usize) -> Arc<Vec<u8>> {
+        let mut data = vec![flags, packet_type, 0, 0, 0];
+        data.resize(len, 0xAA);
+        Arc::new(flv_tag(FlvTagType::Video, timestamp, &data))
+    }
+
+    fn header() -> Arc<Vec<u8>> {
+        video(0, 0x17, 0, 16)
+    }
+
+    fn keyframe(timestamp: u32) -> Arc<Vec<u8>> {
+        video(timestamp, 0x17, 1, 64)
+    }
+
+    fn frame(timestamp: u32, len: usize) -> Arc<Vec<u8>> {
+        video(timestamp, 0x27, 1, len)
+    }
+
+    #[test]
+    fn classifies_tags() {
+        assert_eq!(tag_kind(&header()), TagKind::VideoHeader);
+        assert_eq!(tag_kind(&keyframe(0)), TagKind::Keyframe);
+        assert_eq!(tag_kind(&frame(0, 16)), TagKind::Frame);
+        let audio_header = flv_tag(FlvTagType::Audio, 0, &[0xAF, 0, 0x12, 0x10]);
+        assert_eq!(tag_kind(&audio_header), TagKind::AudioHeader);
+        let metadata = flv_tag(FlvTagType::ScriptData, 0, &[2, 0, 0]);
+        assert_eq!(tag_kind(&metadata), TagKind::Metadata);
+    }
+
+    #[tokio::test]
+    async fn new_viewers_start_at_the_cached_keyframe() {
+        let preview = FlvPreview::new();
+        let tags = [header(), frame(0, 32), keyframe(33), frame(66, 32)];
+        for tag in &tags {
+            preview.push(tag.clone());
+        }
+
+        let (start, mut viewer) = preview.subscribe();
+        // the frame before the keyframe can't be decoded, so it isn't cached
+        let expected = [
+            flv_header(),
+            tags[0].to_vec(),
+            tags[2].to_vec(),
+            tags[3].to_vec(),
+        ]
+        .concat();
+        assert_eq!(start, expected);
+
+        let live = frame(99, 32);
+        preview.push(live.clone());
+        assert_eq!(viewer.next().await, Some(live));
+    }
+
+    #[tokio::test]
+    async fn viewers_without_a_cached_gop_wait_for_a_keyframe() {
+        let preview = FlvPreview::new();
+        preview.push(header());
+        let (start, mut viewer) = preview.subscribe();
+        assert_eq!(start, [flv_header(), header().to_vec()].concat());
+
+        preview.push(frame(0, 32));
+        let key = keyframe(33);
+        preview.push(key.clone());
+        assert_eq!(viewer.next().await, Some(key));
+    }
+
+    #[test]
+    fn drops_a_gop_over_the_cache_limit() {
+        let preview = FlvPreview::new();
+        preview.push(header());
+        preview.push(keyframe(0));
+        let half = GOP_CACHE_LIMIT / 2 + 1;
+        preview.push(frame(33, half));
+        assert_eq!(preview.cache.lock().unwrap().gop.len(), 2);
+        preview.push(frame(66, half));
+        preview.push(frame(99, 32));
+
+        let cache = preview.cache.lock().unwrap();
+        assert!(cache.gop.is_empty());
+        assert!(cache.video_header.is_some());
+        drop(cache);
+        let (start, _viewer) = preview.subscribe();
+        assert_eq!(start, [flv_header(), header().to_vec()].concat());
+
+        // caching starts again at the next keyframe
+        preview.push(keyframe(132));
+        assert_eq!(preview.cache.lock().unwrap().gop.len(), 1);
+    }
+}
@@ -1,166 +1,327 @@
-use super::utils::flv_header;
+use super::{rendition, utils::flv_header};
 
 use crate::{
     config::{self, RelayHandle},
-    db::{self},
-    events::AppEvents,
+    context::AppContext,
+    db::{self, AudioPolicy},
+    events::{AppEvents, EventPublisher},
+    redact,
 };
 use std::{
+    future::Future,
+    pin::Pin,
     process::Stdio,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tauri::{AppHandle, Emitter, Manager};
 use tokio::{
     io::AsyncWriteExt,
-    process::Command,
+    process::{Child, Command},
     sync::{mpsc, Mutex},
 };
 
-pub async fn start_relay(app: &AppHandle, relay: &db::RelayTarget) {
-    let state = app.state::<Arc<config::AppState>>();
+/// A relay that ran at least this long is started again when it fails
+const RETRY_MIN_UPTIME: Duration = Duration::from_secs(30);
+const RETRY_DELAY: Duration = Duration::from_secs(3);
+
+pub async fn start_relay(app: &AppContext, relay: &db::RelayTarget) {
+    let state = app.state();
     let mut relays = state.relays.lock().await;
 
     if relays.contains_key(&relay.id) {
         eprintln!("⚠️ Relay id:{} already exists", relay.id);
         return;
     }
-    match spawn_relay(app, relay).await {
+    // the boxed error isn't Send, and the match awaits the failed relays lock
+    match spawn_relay(app, relay).await.map_err(|e| e.to_string()) {
         Ok(handle) => {
             relays.insert(relay.id, handle);
+            state
+                .metrics
+                .relay(relay.id)
+                .starts
+                .fetch_add(1, Ordering::Relaxed);
 
-            app.emit(AppEvents::RelayActive.as_str(), relay.id)
-                .unwrap_or_else(|_| {
-                    eprintln!("⚠️ Failed to emit active event for relay id:{}", relay.id);
-                });
+            app.publish(AppEvents::RelayActive, relay.id);
+            if state.failed_relays.lock().await.remove(&relay.id) {
+                app.publish(AppEvents::RelayRecovered, relay.id);
+            }
             println!("🟢 Started relay id:{}", relay.id);
         }
         Err(e) => eprintln!("❌ Failed to start relay id:{}: {}", relay.id, e),
     }
 }
 
-pub async fn stop_relay(app: &AppHandle, id: i64) {
-    let state = app.state::<Arc<config::AppState>>();
+pub async fn stop_relay(app: &AppContext, id: i64) {
+    let state = app.state();
     let mut relays = state.relays.lock().await;
     if let Some(handle) = relays.remove(&id) {
+        handle.stopped.store(true, Ordering::SeqCst);
         handle.rx_task.abort();
         state.unregister_relay_channel(id).await;
+        rendition::detach_relay(app, id).await;
         let _ = handle.process.lock().await.kill().await;
-        app.emit(AppEvents::RelayEnded.as_str(), id)
-            .unwrap_or_else(|_| {
-                eprintln!("⚠️ Failed to emit end event for relay id:{}", id);
-            });
+        app.publish(AppEvents::RelayEnded, id);
         println!("🛑 Stopped relay id: {}", id);
     }
 }
 
-pub async fn start_relays(app: &AppHandle) {
+/// Restarts a relay with fresh settings if it is currently running.
+pub async fn restart_relay_if_active(app: &AppContext, relay: &db::RelayTarget) {
+    let state = app.state();
+    let is_active = state.relays.lock().await.contains_key(&relay.id);
+    if is_active {
+        println!("🔁 Restarting relay id:{} with new settings", relay.id);
+        stop_relay(app, relay.id).await;
+        start_relay(app, relay).await;
+    }
+}
+
+pub async fn start_relays(app: &AppContext) {
     let pool = db::get_db_pool();
     let targets = db::get_active_relay_targets(pool).await.unwrap_or_default();
-    print!("{:?}", targets);
     for relay in targets {
         start_relay(app, &relay).await;
     }
 }
 
-pub async fn stop_relays(app: &AppHandle) {
-    let state = app.state::<Arc<config::AppState>>();
-    let mut relays = state.relays.lock().await;
-    for (_, handle) in relays.iter() {
-        stop_relay(app, handle.id).await;
+pub async fn stop_relays(app: &AppContext) {
+    let state = app.state();
+    // stop_relay takes the relays lock itself, so collect ids first
+    let ids: Vec<i64> = state.relays.lock().await.keys().copied().collect();
+    for id in ids {
+        stop_relay(app, id).await;
+    }
+}
+
+pub async fn start_relay_group(app: &AppContext, group_id: i64) -> Result<(), sqlx::Error> {
+    let pool = db::get_db_pool();
+    let targets = db::get_relay_group_targets(group_id, pool).await?;
+    for relay in targets {
+        start_relay(app, &relay).await;
+    }
+    Ok(())
+}
+
+pub async fn stop_relay_group(app: &AppContext, group_id: i64) -> Result<(), sqlx::Error> {
+    let pool = db::get_db_pool();
+    let targets = db::get_relay_group_targets(group_id, pool).await?;
+    for relay in targets {
+        stop_relay(app, relay.id).await;
+    }
+    Ok(())
+}
+
+fn relay_args(relay: &db::RelayTarget, audio_bitrate: u32) -> Vec<String> {
+    let mut args: Vec<String> = ["-f", "flv", "-i", "pipe:0"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let audio_bitrate = format!("{}k", audio_bitrate);
+    match relay.audio_policy {
+        AudioPolicy::Passthrough => {
+            args.extend(["-c:v", "copy", "-c:a", "copy"].map(String::from));
+        }
+        AudioPolicy::Mute | AudioPolicy::Replace => {
+            // silence comes in as its own input, so muting works on sources without audio
+            let file = match relay.audio_policy {
+                AudioPolicy::Replace => relay.audio_file.as_deref(),
+                _ => None,
+            };
+            match file {
+                Some(file) => {
+                    args.extend(["-stream_loop", "-1", "-re", "-i", file].map(String::from))
+                }
+                None => args.extend(
+                    [
+                        "-f",
+                        "lavfi",
+                        "-i",
+                        "anullsrc=channel_layout=stereo:sample_rate=48000",
+                    ]
+                    .map(String::from),
+                ),
+            }
+            args.extend(
+                [
+                    "-map",
+                    "0:v",
+                    "-map",
+                    "1:a",
+                    "-c:v",
+                    "copy",
+                    "-c:a",
+                    "aac",
+                    "-b:a",
+                    audio_bitrate.as_str(),
+                    "-shortest",
+                ]
+                .map(String::from),
+            );
+        }
     }
-    relays.clear();
+    args.extend([
+        "-f".into(),
+        "flv".into(),
+        format!("{}/{}", relay.url, relay.stream_key),
+    ]);
+    args
+}
+
+/// Takes a failed relay out of the running set so it can be started again.
+/// False when it was stopped or replaced in the meantime.
+async fn remove_failed_relay(app: &AppContext, id: i64, process: &Arc<Mutex<Child>>) -> bool {
+    let state = app.state();
+    let handle = {
+        let mut relays = state.relays.lock().await;
+        match relays.get(&id) {
+            Some(handle)
+                if Arc::ptr_eq(&handle.process, process)
+                    && !handle.stopped.load(Ordering::SeqCst) =>
+            {
+                relays.remove(&id)
+            }
+            _ => None,
+        }
+    };
+    let Some(handle) = handle else {
+        return false;
+    };
+    handle.rx_task.abort();
+    state.unregister_relay_channel(id).await;
+    rendition::detach_relay(app, id).await;
+    true
+}
+
+/// Starts a failed relay again from its stored settings, which publishes
+/// `RelayRecovered` once it is up. Boxed because the relay it starts monitors
+/// itself with this same function.
+fn restart_failed_relay(app: AppContext, id: i64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        match db::get_relay_target(id, db::get_db_pool()).await {
+            Ok(target) if target.enabled => {
+                println!("🔁 Restarting failed relay id:{}", id);
+                start_relay(&app, &target).await;
+            }
+            Ok(_) => println!("⏸️ Relay id:{} was disabled, not restarting it", id),
+            Err(e) => eprintln!("❌ Failed to load relay id:{} for a restart: {}", id, e),
+        }
+    })
 }
 
 async fn spawn_relay(
-    app: &AppHandle,
+    app: &AppContext,
     relay: &db::RelayTarget,
 ) -> Result<RelayHandle, Box<dyn std::error::Error>> {
     let id = relay.id;
-    let log_dir = config::log_output_dir(app);
-    let log_file = std::fs::File::create(log_dir.join(format!("relay_{id}.log")))?;
-    let log_file = Stdio::from(log_file);
+    let log_path = config::log_output_dir(app).join(format!("relay_{id}.log"));
+    let state = app.state();
+    state.redactor.add_key(&relay.stream_key);
 
+    let audio_bitrate = state.encoder_settings.lock().await.audio_bitrate;
     let mut child = Command::new("ffmpeg")
-        .args([
-            "-f",
-            "flv",
-            "-i",
-            "pipe:0",
-            "-c:v",
-            "copy",
-            "-c:a",
-            "copy",
-            "-f",
-            "flv",
-            &format!("{}/{}", relay.url, relay.stream_key),
-        ])
+        .args(relay_args(relay, audio_bitrate))
         .stdin(Stdio::piped())
         .stdout(Stdio::null())
-        .stderr(log_file)
+        .stderr(Stdio::piped())
         .spawn()?;
 
+    let stderr = child.stderr.take().unwrap();
+    redact::pipe_to_log(stderr, log_path, state.redactor.clone());
     let mut stdin = child.stdin.take().unwrap();
     let shared_child = Arc::new(Mutex::new(child));
-    let state = app.state::<Arc<config::AppState>>();
-    let headers = state.encoder_sequence_headers.lock().await.clone();
+    let (tx, mut rx) = mpsc::channel(4096);
+    // transformed targets are fed from their own rendition instead of the program feed
+    let headers = if relay.transform.is_identity() {
+        state.encoder_sequence_headers.lock().await.clone()
+    } else {
+        match rendition::attach_relay(app, &relay.transform, id, tx.clone()).await {
+            Ok(headers) => headers,
+            Err(e) => {
+                let _ = shared_child.lock().await.kill().await;
+                return Err(e);
+            }
+        }
+    };
     stdin.write_all(&flv_header()).await?;
     for tag in headers {
         stdin.write_all(&tag).await?;
     }
 
-    let (tx, mut rx) = mpsc::channel(4096);
     // let tx_clone = tx.clone();
-    state.register_relay_channel(id, tx).await;
+    if relay.transform.is_identity() {
+        state.register_relay_channel(id, tx).await;
+    }
+    let counters = state.metrics.relay(id);
     let task = tokio::spawn(async move {
         while let Some(data) = rx.recv().await {
             if let Err(e) = stdin.write_all(&data).await {
                 eprintln!("⚠️ Relay write failed: {}", e);
                 break;
             }
+            counters
+                .bytes
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
         }
     });
     // Task to monitor child process
-    let id_clone = id;
     let child_monitor = shared_child.clone();
-    let retrying = Arc::new(AtomicBool::new(false));
-    // let relay_clone = relay.clone();
-    let retrying_clone = retrying.clone();
     let app_clone = app.clone();
+    let stopped = Arc::new(AtomicBool::new(false));
+    let stopped_clone = stopped.clone();
+    let started_at = Instant::now();
     tokio::spawn(async move {
-        let mut child = child_monitor.lock().await;
-        match child.wait().await {
+        // poll instead of wait() so stop_relay can take the lock to kill the process
+        let exit = loop {
+            let status = child_monitor.lock().await.try_wait();
+            match status {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => tokio::time::sleep(Duration::from_millis(500)).await,
+                Err(e) => break Err(e),
+            }
+        };
+        if stopped_clone.load(Ordering::SeqCst) {
+            return;
+        }
+        let reason = match exit {
             Ok(status) if status.success() => {
-                println!("✅ Relay {} exited normally", id_clone);
-                let _ = app_clone.emit(AppEvents::RelayEnded.as_str(), id_clone);
+                println!("✅ Relay {} exited normally", id);
+                app_clone.publish(AppEvents::RelayEnded, id);
+                return;
             }
             Ok(status) => {
-                eprintln!("❌ Relay {} exited with code {:?}", id_clone, status.code());
-                let _ = app_clone.emit(
-                    AppEvents::RelayFailed.as_str(),
-                    (id_clone, format!("Exited with code {:?}", status.code())),
-                );
-                if !retrying_clone.swap(true, Ordering::SeqCst) {
-                    tokio::time::sleep(Duration::from_secs(3)).await;
-
-                    // Retry spawn
-                    // spawn_relay(&app_clone, &relay_clone).await;
-
-                    println!("🔁 Relay {} restarted", id_clone);
-
-                    retrying_clone.store(false, Ordering::SeqCst);
-                }
+                eprintln!("❌ Relay {} exited with code {:?}", id, status.code());
+                format!("Exited with code {:?}", status.code())
             }
             Err(e) => {
-                eprintln!("❌ Failed to wait on relay {}: {}", id_clone, e);
-                let _ = app_clone.emit(
-                    AppEvents::RelayFailed.as_str(),
-                    (id_clone, format!("Wait error: {}", e)),
-                );
+                eprintln!("❌ Failed to wait on relay {}: {}", id, e);
+                format!("Wait error: {}", e)
             }
+        };
+        let state = app_clone.state();
+        state
+            .metrics
+            .relay(id)
+            .failures
+            .fetch_add(1, Ordering::Relaxed);
+        state.failed_relays.lock().await.insert(id);
+        app_clone.publish(AppEvents::RelayFailed, (id, reason));
+
+        // a relay that dies right away has bad settings and would only fail again
+        let retry = started_at.elapsed() >= RETRY_MIN_UPTIME;
+        if retry {
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+        if !remove_failed_relay(&app_clone, id, &child_monitor).await {
+            return;
+        }
+        if retry {
+            restart_failed_relay(app_clone, id).await;
+        } else {
+            eprintln!("⚠️ Relay {} failed right after starting, not retrying", id);
         }
     });
 
@@ -168,6 +329,7 @@ async fn spawn_relay(
         id,
         process: shared_child,
         rx_task: task,
+        stopped,
         // tx,
     })
 }
@@ -0,0 +1,185 @@
+use super::{encoder, fanout::fanout, utils::flv_header};
+
+use crate::{
+    config::{self, RenditionHandle},
+    context::AppContext,
+    db::OutputTransform,
+    events::{AppEvents, EventPublisher},
+    redact,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    process::Stdio,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::{mpsc, Mutex},
+};
+
+// FLV header plus PreviousTagSize0
+const FLV_HEADER_LEN: usize = 13;
+
+/// Subscribes a relay to the rendition for `transform`, starting it if needed.
+/// Returns the rendition's cached sequence headers for the relay to send first.
+pub async fn attach_relay(
+    app: &AppContext,
+    transform: &OutputTransform,
+    id: i64,
+    tx: mpsc::Sender<Arc<Vec<u8>>>,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let filter = encoder::transform_filter(transform)?;
+    let state = app.state();
+    let mut renditions = state.renditions.lock().await;
+    if !renditions.contains_key(&filter) {
+        let handle = spawn_rendition(app, &filter).await?;
+        println!("🎞️ Started rendition {}", filter);
+        renditions.insert(filter.clone(), handle);
+    }
+    let rendition = &renditions[&filter];
+    rendition.channels.lock().await.insert(id, tx);
+    let headers = rendition.sequence_headers.lock().await.clone();
+    Ok(headers)
+}
+
+/// Unsubscribes a relay and stops any rendition left without relays.
+pub async fn detach_relay(app: &AppContext, id: i64) {
+    let state = app.state();
+    let mut renditions = state.renditions.lock().await;
+    let mut unused = vec![];
+    for (filter, rendition) in renditions.iter() {
+        let mut channels = rendition.channels.lock().await;
+        channels.remove(&id);
+        if channels.is_empty() {
+            unused.push(filter.clone());
+        }
+    }
+    for filter in unused {
+        if let Some(rendition) = renditions.remove(&filter) {
+            rendition.input_task.abort();
+            rendition.fanout_task.abort();
+            let _ = rendition.process.lock().await.kill().await;
+            println!("🛑 Stopped rendition {}", filter);
+        }
+    }
+}
+
+/// Short id for a rendition's filter, for log file names and metric labels.
+pub fn rendition_id(filter: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    filter.hash(&mut hasher);
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+async fn spawn_rendition(
+    app: &AppContext,
+    filter: &str,
+) -> Result<RenditionHandle, Box<dyn std::error::Error>> {
+    let state = app.state();
+    let settings = state.encoder_settings.lock().await.clone();
+    let log_path =
+        config::log_output_dir(app).join(format!("rendition_{}.log", rendition_id(filter)));
+
+    let mut child = Command::new("ffmpeg")
+        .args(encoder::rendition_args(&settings, filter))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take().unwrap();
+    redact::pipe_to_log(stderr, log_path, state.redactor.clone());
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    let headers = state.encoder_sequence_headers.lock().await.clone();
+    stdin.write_all(&flv_header()).await?;
+    for tag in headers {
+        stdin.write_all(&tag).await?;
+    }
+
+    let (input, mut rx) = mpsc::channel::<Arc<Vec<u8>>>(4096);
+    let input_task = tokio::spawn(async move {
+        while let Some(data) = rx.recv().await {
+            if let Err(e) = stdin.write_all(&data).await {
+                eprintln!("⚠️ Rendition write failed: {}", e);
+                break;
+            }
+        }
+    });
+
+    let sequence_headers = Arc::new(Mutex::new(vec![]));
+    let channels = Arc::new(Mutex::new(HashMap::new()));
+    let headers_clone = sequence_headers.clone();
+    let channels_clone = channels.clone();
+    let fanout_task = tokio::spawn(async move {
+        // relays write their own FLV header, so drop the one ffmpeg starts with
+        let mut header = [0u8; FLV_HEADER_LEN];
+        if stdout.read_exact(&mut header).await.is_err() {
+            eprintln!("🔚 Rendition stdout closed before any output");
+            return;
+        }
+        fanout(stdout, &headers_clone, &channels_clone, None, None).await;
+    });
+
+    let process = Arc::new(Mutex::new(child));
+    monitor_rendition(app.clone(), filter.to_string(), process.clone());
+
+    Ok(RenditionHandle {
+        process,
+        input,
+        input_task,
+        fanout_task,
+        sequence_headers,
+        channels,
+    })
+}
+
+/// Watches a rendition's ffmpeg the way relays are watched. One that dies on its
+/// own leaves its targets without data, so they are reported as failed.
+fn monitor_rendition(app: AppContext, filter: String, process: Arc<Mutex<Child>>) {
+    tokio::spawn(async move {
+        // poll instead of wait() so detach_relay can take the lock to kill the process
+        let exit = loop {
+            let status = process.lock().await.try_wait();
+            match status {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => tokio::time::sleep(Duration::from_millis(500)).await,
+                Err(e) => break Err(e),
+            }
+        };
+        let state = app.state();
+        let mut renditions = state.renditions.lock().await;
+        // detach_relay removes a rendition before it stops it
+        let current = renditions
+            .get(&filter)
+            .is_some_and(|rendition| Arc::ptr_eq(&rendition.process, &process));
+        if !current {
+            return;
+        }
+        let Some(rendition) = renditions.remove(&filter) else {
+            return;
+        };
+        drop(renditions);
+        rendition.input_task.abort();
+        rendition.fanout_task.abort();
+        let reason = match exit {
+            Ok(status) => format!("Rendition exited with code {:?}", status.code()),
+            Err(e) => format!("Rendition wait error: {}", e),
+        };
+        eprintln!("❌ Rendition {} stopped: {}", filter, reason);
+        let ids: Vec<i64> = rendition.channels.lock().await.keys().copied().collect();
+        for id in ids {
+            state
+                .metrics
+                .relay(id)
+                .failures
+                .fetch_add(1, Ordering::Relaxed);
+            state.failed_relays.lock().await.insert(id);
+            app.publish(AppEvents::RelayFailed, (id, reason.clone()));
+        }
+    });
+}
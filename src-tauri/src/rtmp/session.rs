@@ -1,27 +1,100 @@
 use super::{
+    analyzer::IngestAnalyzer,
     encoder,
-    utils::{flv_tag, FlvTagType},
+    source::{self, ConnectReader, SourceInfo},
+    utils::{flv_tag, read_socket, FlvTagType, SocketRead},
 };
 
 use crate::{
     config,
-    events::AppEvents,
+    context::AppContext,
+    events::{AppEvents, EventPublisher},
     rtmp::{relay, utils::create_metadata_tag},
 };
 use rml_rtmp::sessions::{
     ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
 };
-use std::sync::Arc;
-use tauri::{async_runtime, AppHandle, Emitter, Manager};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutStage {
+    Handshake,
+    /// Connected but never started publishing
+    Publish,
+    /// Publishing but no audio or video arrived
+    Idle,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct IngestTimeout {
+    stage: TimeoutStage,
+    timeout_secs: u32,
+}
+
+/// Returns when a timeout of `secs` started at `from` expires, or None when it is disabled.
+pub fn deadline(from: Instant, secs: u32) -> Option<Instant> {
+    (secs > 0).then(|| from + Duration::from_secs(secs as u64))
+}
+
+pub fn report_timeout(app: &AppContext, stage: TimeoutStage, timeout_secs: u32) {
+    eprintln!("⏱️ Ingest {:?} timed out after {}s", stage, timeout_secs);
+    app.publish(
+        AppEvents::IngestTimeout,
+        IngestTimeout {
+            stage,
+            timeout_secs,
+        },
+    );
+}
 
 pub async fn handle_session(
-    app: &AppHandle,
+    app: &AppContext,
+    socket: TcpStream,
+    received_data: Vec<u8>,
+    cancel: CancellationToken,
+    connected_at: Instant,
+) -> Result<(), Box<dyn std::error::Error>> {
+    run_until_closed(app, socket, received_data, cancel, connected_at, None).await
+}
+
+/// Runs the session, then stops everything fed by a publish that was accepted,
+/// whether the session ended cleanly, timed out, was cut off or failed.
+async fn run_until_closed(
+    app: &AppContext,
+    socket: TcpStream,
+    received_data: Vec<u8>,
+    cancel: CancellationToken,
+    connected_at: Instant,
+    mut published_key: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let result = run_session(
+        app,
+        socket,
+        received_data,
+        cancel,
+        connected_at,
+        &mut published_key,
+    )
+    .await
+    // a String can be held across the await below, the boxed error isn't Send
+    .map_err(|e| e.to_string());
+    if let Some(stream_key) = published_key {
+        end_stream(app, stream_key).await;
+    }
+    result.map_err(Into::into)
+}
+
+async fn run_session(
+    app: &AppContext,
     mut socket: TcpStream,
     mut received_data: Vec<u8>,
+    cancel: CancellationToken,
+    connected_at: Instant,
+    published_key: &mut Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("📦 Starting RTMP session");
     let config = ServerSessionConfig::new();
@@ -37,11 +110,41 @@ pub async fn handle_session(
     }
 
     let mut buffer = [0u8; 4096];
+    let mut connect_reader = ConnectReader::new();
+    let mut analyzer = IngestAnalyzer::new();
+    let mut last_media = Instant::now();
+    let settings = app.state().ingest_settings.lock().await.clone();
+    let remote_addr = socket.peer_addr().ok().map(|addr| addr.to_string());
+    source::update_source_info(app, |info| *info = SourceInfo::new(remote_addr)).await;
 
     loop {
         // Read more if we’ve exhausted the buffer
         if received_data.is_empty() {
-            let n = socket.read(&mut buffer).await?;
+            let (stage, timeout_secs, deadline) = match published_key {
+                None => (
+                    TimeoutStage::Publish,
+                    settings.publish_timeout_secs,
+                    deadline(connected_at, settings.publish_timeout_secs),
+                ),
+                Some(_) => (
+                    TimeoutStage::Idle,
+                    settings.idle_timeout_secs,
+                    deadline(last_media, settings.idle_timeout_secs),
+                ),
+            };
+            let n = match read_socket(&mut socket, &mut buffer, &cancel, deadline).await {
+                SocketRead::Data(result) => result?,
+                SocketRead::Cancelled => {
+                    println!("✂️ Disconnecting source on request");
+                    let _ = socket.shutdown().await;
+                    return Ok(());
+                }
+                SocketRead::TimedOut => {
+                    report_timeout(app, stage, timeout_secs);
+                    let _ = socket.shutdown().await;
+                    return Ok(());
+                }
+            };
             if n == 0 {
                 println!("🔌 Client disconnected.");
                 return Ok(());
@@ -49,6 +152,10 @@ pub async fn handle_session(
             received_data.extend_from_slice(&buffer[..n]);
         }
 
+        if let Some(command_object) = connect_reader.feed(&received_data) {
+            source::update_source_info(app, |info| info.apply_connect(&command_object)).await;
+        }
+
         match session.handle_input(&received_data) {
             Ok(results) => {
                 received_data.clear();
@@ -59,7 +166,23 @@ pub async fn handle_session(
                             socket.write_all(&packet.bytes).await?;
                         }
                         ServerSessionResult::RaisedEvent(event) => {
-                            match handle_session_event(&app, &mut session, event).await {
+                            if matches!(
+                                event,
+                                ServerSessionEvent::AudioDataReceived { .. }
+                                    | ServerSessionEvent::VideoDataReceived { .. }
+                                    | ServerSessionEvent::PublishStreamRequested { .. }
+                            ) {
+                                last_media = Instant::now();
+                            }
+                            match handle_session_event(
+                                &app,
+                                &mut session,
+                                &mut analyzer,
+                                published_key,
+                                event,
+                            )
+                            .await
+                            {
                                 Ok(responses) => {
                                     for res in responses {
                                         if let ServerSessionResult::OutboundResponse(packet) = res {
@@ -86,9 +209,35 @@ pub async fn handle_session(
     }
 }
 
+async fn publish_source_stats(app: &AppContext, analyzer: &mut IngestAnalyzer) {
+    let Some((stats, new_warnings)) = analyzer.tick() else {
+        return;
+    };
+    for warning in new_warnings {
+        eprintln!("⚠️ Source: {}", warning);
+        app.publish(AppEvents::SourceWarning, warning);
+    }
+    app.publish(AppEvents::SourceStats, &stats);
+    let state = app.state();
+    *state.source_stats.lock().await = Some(stats);
+}
+
+/// Stops everything fed by the source once a publish is over.
+async fn end_stream(app: &AppContext, stream_key: String) {
+    println!("🛑 Stream ended. Closing ffmpeg.");
+    relay::stop_relays(app).await;
+    encoder::stop_encoder(app).await;
+    *app.state().source_stats.lock().await = None;
+    source::update_source_info(app, |info| info.publishing = false).await;
+    // the payload reaches the webview and webhooks, so only the masked key goes out
+    app.publish(AppEvents::StreamEnded, config::mask_key(&stream_key));
+}
+
 async fn handle_session_event(
-    app: &AppHandle,
+    app: &AppContext,
     session: &mut ServerSession,
+    analyzer: &mut IngestAnalyzer,
+    published_key: &mut Option<String>,
     event: ServerSessionEvent,
 ) -> Result<Vec<ServerSessionResult>, Box<dyn std::error::Error + Send + Sync>> {
     match event {
@@ -101,6 +250,7 @@ async fn handle_session_event(
                 "🌐 Connection requested for app: {}: {}",
                 app_name, request_id
             );
+            source::update_source_info(app, |info| info.app_name = Some(app_name)).await;
             Ok(session.accept_request(request_id)?)
         }
 
@@ -115,8 +265,10 @@ async fn handle_session_event(
                     println!("🎥 FFMPEG started");
                     // wait for playlist to be created in new thread
                     let app_clone = app.clone();
-                    async_runtime::spawn(async move {
-                        let playlist_path = config::hls_playlist_path(&app_clone);
+                    tokio::spawn(async move {
+                        let preview_mode =
+                            app_clone.state().encoder_settings.lock().await.preview_mode;
+                        let playlist_path = config::preview_ready_path(&app_clone, preview_mode);
                         use tokio::time::{sleep, Duration};
                         let mut attempts = 0;
                         while !playlist_path.exists() && attempts < 50 {
@@ -125,13 +277,19 @@ async fn handle_session_event(
                         }
                         if playlist_path.exists() {
                             println!("✅ FFMPEG started successfully");
-                            let _ = app_clone.emit(AppEvents::StreamPreviewActive.as_str(), ());
+                            app_clone.publish(AppEvents::StreamPreviewActive, ());
                         } else {
                             eprintln!("⚠️ FFMPEG failed to create hls stream");
-                            let _ = app_clone.emit(AppEvents::StreamPreviewFailed.as_str(), ());
+                            app_clone.publish(AppEvents::StreamPreviewFailed, ());
                         }
                     });
-                    let _ = app.emit(AppEvents::StreamActive.as_str(), ());
+                    app.publish(AppEvents::StreamActive, ());
+                    source::update_source_info(app, |info| {
+                        info.stream_key = Some(config::mask_key(&stream_key));
+                        info.publishing = true;
+                    })
+                    .await;
+                    *published_key = Some(stream_key);
                     Ok(session.accept_request(request_id)?)
                 }
                 Err(e) => {
@@ -146,7 +304,9 @@ async fn handle_session_event(
             data, timestamp, ..
         } => {
             // println!("🎵 Audio data received: {} bytes", data.len());
-            let state = app.state::<Arc<config::AppState>>();
+            analyzer.on_audio(timestamp.value, &data);
+            publish_source_stats(app, analyzer).await;
+            let state = app.state();
             let tagged_data = flv_tag(FlvTagType::Audio, timestamp.value, &data);
             let mut guard = state.encoder_stdin.lock().await;
             if let Some(stdin) = guard.as_mut() {
@@ -161,7 +321,9 @@ async fn handle_session_event(
             data, timestamp, ..
         } => {
             // println!("📹 Video data received: {} bytes", data.len());
-            let state = app.state::<Arc<config::AppState>>();
+            analyzer.on_video(timestamp.value, &data);
+            publish_source_stats(app, analyzer).await;
+            let state = app.state();
             let tagged_data = flv_tag(FlvTagType::Video, timestamp.value, &data);
             let mut guard = state.encoder_stdin.lock().await;
             if let Some(stdin) = guard.as_mut() {
@@ -179,13 +341,14 @@ async fn handle_session_event(
         } => {
             println!("📊 Metadata for stream {}: {:?}", stream_key, metadata);
             let tagged_data = create_metadata_tag(&metadata);
-            let state = app.state::<Arc<config::AppState>>();
+            let state = app.state();
             let mut guard = state.encoder_stdin.lock().await;
             if let Some(stdin) = guard.as_mut() {
                 if let Err(e) = stdin.write_all(&tagged_data).await {
                     eprintln!("❌ Failed to write to encoder stdin: {}", e);
                 }
             }
+            source::update_source_info(app, |info| info.apply_metadata(&metadata)).await;
             *state.source_metadata.lock().await = Some(metadata.clone());
             // println!("Metadata: {}", metadata.());
             Ok(vec![])
@@ -200,10 +363,8 @@ async fn handle_session_event(
                 "📴 Publish finished for stream '{}' (id {})",
                 app_name, stream_key
             );
-            println!("🛑 Stream ended. Closing ffmpeg.");
-            relay::stop_relays(&app).await;
-            encoder::stop_encoder(&app).await;
-            app.emit(AppEvents::StreamEnded.as_str(), stream_key)?;
+            *published_key = None;
+            end_stream(app, stream_key).await;
             // Optionally: clean up any associated buffers, files, etc.
 
             Ok(vec![])
@@ -215,3 +376,71 @@ async fn handle_session_event(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::AppState,
+        context::AppPaths,
+        events::{BusEvent, EventReceiver},
+    };
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    async fn wait_for(events: &mut EventReceiver, event: AppEvents) -> BusEvent {
+        let name = event.as_str();
+        let next = async {
+            loop {
+                let received = events.recv().await.unwrap();
+                if received.event == name {
+                    return received;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), next)
+            .await
+            .unwrap_or_else(|_| panic!("no {} event", name))
+    }
+
+    fn test_context() -> AppContext {
+        let dir = std::env::temp_dir().join(format!("duplicast-session-{}", std::process::id()));
+        AppContext::new(Arc::new(AppState::new(0, 0)), AppPaths::in_dir(dir))
+    }
+
+    #[tokio::test]
+    async fn read_error_while_publishing_ends_the_stream() {
+        let app = test_context();
+        let mut events = app.state().events.subscribe();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let app_clone = app.clone();
+        let session = tokio::spawn(async move {
+            run_until_closed(
+                &app_clone,
+                socket,
+                vec![],
+                CancellationToken::new(),
+                Instant::now(),
+                Some("test-key".into()),
+            )
+            .await
+            .map_err(|e| e.to_string())
+        });
+        // the session publishes its source info right before it starts reading,
+        // then the connection is reset under it
+        wait_for(&mut events, AppEvents::SourceInfoChanged).await;
+        client.set_linger(Some(Duration::ZERO)).unwrap();
+        drop(client);
+
+        let ended = wait_for(&mut events, AppEvents::StreamEnded).await;
+        assert_eq!(ended.payload, config::mask_key("test-key"));
+        let result = session.await.unwrap();
+        assert!(result.is_err(), "a reset connection should fail the read");
+        assert!(app.state().source_stats.lock().await.is_none());
+    }
+}
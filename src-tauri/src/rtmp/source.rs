@@ -0,0 +1,312 @@
+use crate::{
+    context::AppContext,
+    events::{AppEvents, EventPublisher},
+};
+use rml_amf0::Amf0Value;
+use rml_rtmp::{chunk_io::ChunkDeserializer, messages::RtmpMessage, sessions::StreamMetadata};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What we know about the current publisher, built up as the RTMP session progresses.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceInfo {
+    pub remote_addr: Option<String>,
+    /// Unix time in milliseconds
+    pub connected_at: u64,
+    pub app_name: Option<String>,
+    /// Masked, only the last few characters are shown
+    pub stream_key: Option<String>,
+    pub publishing: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f32>,
+    pub video_codec: Option<String>,
+    pub video_bitrate_kbps: Option<u32>,
+    pub audio_codec: Option<String>,
+    pub audio_bitrate_kbps: Option<u32>,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u32>,
+    /// Encoder string the publisher reports, e.g. "obs-output module (libobs version 30.1.2)".
+    /// Until its metadata arrives, the one named in the connect command's flashVer.
+    pub encoder: Option<String>,
+    /// Client version from the connect command, e.g. "FMLE/3.0 (compatible; FMSc/1.0)"
+    pub flash_ver: Option<String>,
+}
+
+impl SourceInfo {
+    pub fn new(remote_addr: Option<String>) -> Self {
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Self {
+            remote_addr,
+            connected_at,
+            ..Default::default()
+        }
+    }
+
+    pub fn apply_metadata(&mut self, metadata: &StreamMetadata) {
+        self.width = metadata.video_width;
+        self.height = metadata.video_height;
+        self.frame_rate = metadata.video_frame_rate;
+        self.video_codec = metadata.video_codec_id.map(video_codec_name);
+        self.video_bitrate_kbps = metadata.video_bitrate_kbps;
+        self.audio_codec = metadata.audio_codec_id.map(audio_codec_name);
+        self.audio_bitrate_kbps = metadata.audio_bitrate_kbps;
+        self.audio_sample_rate = metadata.audio_sample_rate;
+        self.audio_channels = metadata.audio_channels;
+        if metadata.encoder.is_some() {
+            self.encoder = metadata.encoder.clone();
+        }
+    }
+
+    /// Takes what the client tells about itself in its connect command.
+    pub fn apply_connect(&mut self, command_object: &Amf0Value) {
+        let Amf0Value::Object(properties) = command_object else {
+            return;
+        };
+        if let Some(Amf0Value::Utf8String(flash_ver)) = properties.get("flashVer") {
+            self.flash_ver = Some(flash_ver.clone());
+            if self.encoder.is_none() {
+                self.encoder = encoder_from_flash_ver(flash_ver);
+            }
+        }
+    }
+}
+
+/// Publishers put their own name after "compatible;", e.g. "FMLE/3.0 (compatible; Lavf61.1.100)".
+fn encoder_from_flash_ver(flash_ver: &str) -> Option<String> {
+    let (_, rest) = flash_ver.split_once("compatible;")?;
+    let encoder = rest.split(')').next()?.trim();
+    (!encoder.is_empty()).then(|| encoder.to_string())
+}
+
+/// Enhanced RTMP sends a FourCC where FLV has a small codec id.
+fn fourcc(id: u32) -> Option<String> {
+    let bytes = id.to_be_bytes();
+    bytes
+        .iter()
+        .all(|b| b.is_ascii_graphic())
+        .then(|| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn video_codec_name(id: u32) -> String {
+    let name = match id {
+        2 => "Sorenson H.263",
+        3 => "Screen Video",
+        4 => "VP6",
+        5 => "VP6 Alpha",
+        6 => "Screen Video 2",
+        7 => "H.264",
+        12 => "HEVC",
+        _ => match fourcc(id).as_deref() {
+            Some("avc1") => "H.264",
+            Some("hvc1") => "HEVC",
+            Some("av01") => "AV1",
+            Some("vp09") => "VP9",
+            Some(other) => return other.to_string(),
+            None => return format!("Unknown ({})", id),
+        },
+    };
+    name.to_string()
+}
+
+fn audio_codec_name(id: u32) -> String {
+    let name = match id {
+        0 | 3 => "PCM",
+        1 => "ADPCM",
+        2 | 14 => "MP3",
+        4..=6 => "Nellymoser",
+        7 => "G.711 A-law",
+        8 => "G.711 mu-law",
+        10 => "AAC",
+        11 => "Speex",
+        _ => match fourcc(id).as_deref() {
+            Some("mp4a") => "AAC",
+            Some("Opus") => "Opus",
+            Some(".mp3") => "MP3",
+            Some("fLaC") => "FLAC",
+            Some("ac-3") => "AC-3",
+            Some("ec-3") => "E-AC-3",
+            Some(other) => return other.to_string(),
+            None => return format!("Unknown ({})", id),
+        },
+    };
+    name.to_string()
+}
+
+/// rml_rtmp doesn't pass on the connect command's properties, so this reads the
+/// client's first messages alongside the session until the connect command shows up.
+pub struct ConnectReader {
+    deserializer: Option<ChunkDeserializer>,
+}
+
+impl Default for ConnectReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectReader {
+    pub fn new() -> Self {
+        Self {
+            deserializer: Some(ChunkDeserializer::new()),
+        }
+    }
+
+    /// Feeds bytes the client sent after the handshake. Returns the connect
+    /// command's object once, and ignores everything after it.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<Amf0Value> {
+        let deserializer = self.deserializer.as_mut()?;
+        let mut input = bytes;
+        loop {
+            let payload = match deserializer.get_next_message(input) {
+                Ok(Some(payload)) => payload,
+                Ok(None) => return None,
+                Err(_) => {
+                    self.deserializer = None;
+                    return None;
+                }
+            };
+            // the deserializer keeps what it hasn't used yet
+            input = &[];
+            match payload.to_rtmp_message() {
+                Ok(RtmpMessage::SetChunkSize { size }) => {
+                    if deserializer.set_max_chunk_size(size as usize).is_err() {
+                        self.deserializer = None;
+                        return None;
+                    }
+                }
+                Ok(RtmpMessage::Amf0Command {
+                    command_name,
+                    command_object,
+                    ..
+                }) if command_name == "connect" => {
+                    self.deserializer = None;
+                    return Some(command_object);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Applies `update` to the current source info and tells the frontend about it.
+pub async fn update_source_info(app: &AppContext, update: impl FnOnce(&mut SourceInfo)) {
+    let state = app.state();
+    let mut guard = state.source_info.lock().await;
+    let info = guard.get_or_insert_with(|| SourceInfo::new(None));
+    update(info);
+    app.publish(AppEvents::SourceInfoChanged, &*info);
+}
+
+pub async fn clear_source_info(app: &AppContext) {
+    let state = app.state();
+    if state.source_info.lock().await.take().is_some() {
+        app.publish(AppEvents::SourceInfoChanged, None::<SourceInfo>);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rml_rtmp::{chunk_io::ChunkSerializer, time::RtmpTimestamp};
+    use std::collections::HashMap;
+
+    fn connect_bytes(flash_ver: &str) -> Vec<u8> {
+        let mut serializer = ChunkSerializer::new();
+        // a larger chunk size than the default 128, as OBS sends before connecting
+        let mut bytes = serializer
+            .set_max_chunk_size(4096, RtmpTimestamp::new(0))
+            .unwrap()
+            .bytes;
+        let properties = HashMap::from([
+            ("app".to_string(), Amf0Value::Utf8String("live".into())),
+            (
+                "flashVer".to_string(),
+                Amf0Value::Utf8String(flash_ver.into()),
+            ),
+            (
+                "tcUrl".to_string(),
+                Amf0Value::Utf8String(format!("rtmp://localhost/live/{}", "x".repeat(300))),
+            ),
+        ]);
+        let connect = RtmpMessage::Amf0Command {
+            command_name: "connect".into(),
+            transaction_id: 1.0,
+            command_object: Amf0Value::Object(properties),
+            additional_arguments: vec![],
+        }
+        .into_message_payload(RtmpTimestamp::new(0), 0)
+        .unwrap();
+        bytes.extend(serializer.serialize(&connect, true, false).unwrap().bytes);
+        bytes
+    }
+
+    #[test]
+    fn reads_the_connect_command_across_reads() {
+        let bytes = connect_bytes("FMLE/3.0 (compatible; FMSc/1.0)");
+        let mut reader = ConnectReader::new();
+        let mut found = vec![];
+        for chunk in bytes.chunks(7) {
+            found.extend(reader.feed(chunk));
+        }
+        assert_eq!(found.len(), 1);
+
+        let mut info = SourceInfo::default();
+        info.apply_connect(&found[0]);
+        assert_eq!(
+            info.flash_ver.as_deref(),
+            Some("FMLE/3.0 (compatible; FMSc/1.0)")
+        );
+        assert_eq!(info.encoder.as_deref(), Some("FMSc/1.0"));
+
+        // done after the connect command
+        assert!(reader.feed(&connect_bytes("FMLE/3.0")).is_none());
+    }
+
+    #[test]
+    fn metadata_encoder_replaces_the_connect_one() {
+        let mut info = SourceInfo {
+            encoder: Some("Lavf61.1.100".into()),
+            ..Default::default()
+        };
+        let mut metadata = StreamMetadata::new();
+        metadata.video_codec_id = Some(7);
+        metadata.audio_codec_id = Some(10);
+        info.apply_metadata(&metadata);
+        assert_eq!(info.encoder.as_deref(), Some("Lavf61.1.100"));
+        assert_eq!(info.video_codec.as_deref(), Some("H.264"));
+        assert_eq!(info.audio_codec.as_deref(), Some("AAC"));
+
+        metadata.encoder = Some("obs-output module (libobs version 30.1.2)".into());
+        info.apply_metadata(&metadata);
+        assert_eq!(
+            info.encoder.as_deref(),
+            Some("obs-output module (libobs version 30.1.2)")
+        );
+    }
+
+    #[test]
+    fn names_codecs_by_id_and_fourcc() {
+        assert_eq!(video_codec_name(7), "H.264");
+        assert_eq!(video_codec_name(u32::from_be_bytes(*b"hvc1")), "HEVC");
+        assert_eq!(video_codec_name(u32::from_be_bytes(*b"av01")), "AV1");
+        assert_eq!(video_codec_name(99), "Unknown (99)");
+        assert_eq!(audio_codec_name(10), "AAC");
+        assert_eq!(audio_codec_name(2), "MP3");
+        assert_eq!(audio_codec_name(u32::from_be_bytes(*b"Opus")), "Opus");
+        assert_eq!(audio_codec_name(u32::from_be_bytes(*b"abcd")), "abcd");
+    }
+
+    #[test]
+    fn takes_the_encoder_from_flash_ver() {
+        assert_eq!(
+            encoder_from_flash_ver("FMLE/3.0 (compatible; Lavf61.1.100)").as_deref(),
+            Some("Lavf61.1.100")
+        );
+        assert_eq!(encoder_from_flash_ver("LNX 9,0,124,2"), None);
+        assert_eq!(encoder_from_flash_ver("FMLE/3.0 (compatible; )"), None);
+    }
+}
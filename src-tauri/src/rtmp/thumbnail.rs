@@ -0,0 +1,180 @@
+use super::preview::{subscribe_preview, PreviewViewer};
+
+use crate::{
+    config::{self, Thumbnail, ThumbnailerHandle},
+    context::AppContext,
+    events::{AppEvents, EventPublisher},
+    redact,
+};
+use bytes::Bytes;
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{ChildStdin, Command},
+};
+
+const THUMBNAIL_WIDTH: u32 = 320;
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Pulls the next complete JPEG (SOI to EOI) out of `buf`. Scan data escapes
+/// 0xFF bytes, so the first EOI after an SOI ends the image.
+fn take_jpeg(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let start = buf.windows(2).position(|w| w == [0xFF, 0xD8])?;
+    let end = buf[start + 2..]
+        .windows(2)
+        .position(|w| w == [0xFF, 0xD9])?
+        + start
+        + 4;
+    let jpeg = buf[start..end].to_vec();
+    buf.drain(..end);
+    Some(jpeg)
+}
+
+/// Writes the preview's start bytes and then every live tag to a side ffmpeg.
+async fn feed_preview(mut stdin: ChildStdin, start: Vec<u8>, mut viewer: PreviewViewer) {
+    if stdin.write_all(&start).await.is_err() {
+        return;
+    }
+    while let Some(tag) = viewer.next().await {
+        if stdin.write_all(&tag).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn thumbnail_args(interval_secs: u32) -> Vec<String> {
+    let filter = format!("fps=1/{},scale={}:-2", interval_secs, THUMBNAIL_WIDTH);
+    [
+        "-f",
+        "flv",
+        "-i",
+        "pipe:0",
+        "-an",
+        "-vf",
+        filter.as_str(),
+        "-c:v",
+        "mjpeg",
+        "-q:v",
+        "5",
+        "-f",
+        "image2pipe",
+        "pipe:1",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Starts a side ffmpeg that turns the program feed into a small JPEG every few
+/// seconds. It reads from the FLV preview, which has to be running already.
+pub async fn start_thumbnailer(app: &AppContext) -> Result<(), Box<dyn std::error::Error>> {
+    let state = app.state();
+    let interval_secs = state.encoder_settings.lock().await.thumbnail_interval_secs;
+    if interval_secs == 0 {
+        return Ok(());
+    }
+    let (start, viewer) = subscribe_preview(app)
+        .await
+        .ok_or("The preview feed is not running")?;
+    let log_path = config::log_output_dir(app).join("ffmpeg_thumbnail.log");
+    let mut child = Command::new("ffmpeg")
+        .args(thumbnail_args(interval_secs))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take().unwrap();
+    redact::pipe_to_log(stderr, log_path, state.redactor.clone());
+    let stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    let input_task = tokio::spawn(feed_preview(stdin, start, viewer));
+    let app_clone = app.clone();
+    let output_task = tokio::spawn(async move {
+        let mut buffer = [0u8; 16 * 1024];
+        let mut pending = vec![];
+        while let Ok(n) = stdout.read(&mut buffer).await {
+            if n == 0 {
+                break;
+            }
+            pending.extend_from_slice(&buffer[..n]);
+            while let Some(jpeg) = take_jpeg(&mut pending) {
+                let captured_at = unix_millis();
+                *app_clone.state().thumbnail.lock().await = Some(Thumbnail {
+                    jpeg: Bytes::from(jpeg),
+                    captured_at,
+                });
+                app_clone.publish(AppEvents::ThumbnailUpdated, captured_at);
+            }
+        }
+    });
+
+    *state.thumbnailer.lock().await = Some(ThumbnailerHandle {
+        process: child,
+        tasks: vec![input_task, output_task],
+    });
+    Ok(())
+}
+
+pub async fn stop_thumbnailer(app: &AppContext) {
+    let state = app.state();
+    if let Some(mut thumbnailer) = state.thumbnailer.lock().await.take() {
+        for task in thumbnailer.tasks {
+            task.abort();
+        }
+        let _ = thumbnailer.process.kill().await;
+    }
+    *state.thumbnail.lock().await = None;
+}
+
+/// Saves one full-resolution frame of the program feed as a JPEG in `dir`.
+pub async fn capture_snapshot(
+    app: &AppContext,
+    dir: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let (start, viewer) = subscribe_preview(app).await.ok_or("No stream is live")?;
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("snapshot-{}.jpg", unix_millis()));
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-f",
+            "flv",
+            "-i",
+            "pipe:0",
+            "-an",
+            "-frames:v",
+            "1",
+            "-q:v",
+            "2",
+            "-y",
+        ])
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdin = child.stdin.take().unwrap();
+    let input_task = tokio::spawn(feed_preview(stdin, start, viewer));
+    let result = tokio::time::timeout(SNAPSHOT_TIMEOUT, child.wait()).await;
+    input_task.abort();
+    match result {
+        Ok(Ok(status)) if status.success() && path.exists() => Ok(path),
+        Ok(Ok(status)) => Err(format!("ffmpeg exited with {}", status).into()),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => {
+            let _ = child.kill().await;
+            Err("Timed out waiting for a video frame".into())
+        }
+    }
+}
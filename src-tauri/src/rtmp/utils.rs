@@ -1,8 +1,16 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+    time::Instant,
+};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use rml_amf0::Amf0Value;
 use rml_rtmp::sessions::StreamMetadata;
+use tokio::{io::AsyncReadExt, net::TcpStream};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, Clone, Copy)]
 pub enum FlvTagType {
@@ -97,4 +105,37 @@ pub fn is_video_keyframe_avc_sequence_header(tag: &[u8]) -> bool {
 
 pub fn is_audio_aac_sequence_header(tag: &[u8]) -> bool {
   tag.len() > 13 && tag[0] == 0x08 && (tag[11] & 0xF0) == 0xA0 && tag[12] == 0
-}
\ No newline at end of file
+}
+
+pub enum SocketRead {
+    Data(std::io::Result<usize>),
+    Cancelled,
+    TimedOut,
+}
+
+/// Reads from the socket until data arrives, `cancel` fires or `deadline` passes.
+pub async fn read_socket(
+    socket: &mut TcpStream,
+    buffer: &mut [u8],
+    cancel: &CancellationToken,
+    deadline: Option<Instant>,
+) -> SocketRead {
+    let mut read = pin!(socket.read(buffer));
+    let mut cancelled = pin!(cancel.cancelled());
+    let mut expired = pin!(async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    });
+    poll_fn(|cx| {
+        if cancelled.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(SocketRead::Cancelled);
+        }
+        if let Poll::Ready(result) = read.as_mut().poll(cx) {
+            return Poll::Ready(SocketRead::Data(result));
+        }
+        expired.as_mut().poll(cx).map(|_| SocketRead::TimedOut)
+    })
+    .await
+}
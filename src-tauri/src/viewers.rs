@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+
+/// HLS players reload every few seconds, so a client quiet for longer has gone away.
+const ACTIVE_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ViewerInfo {
+    pub address: String,
+    /// Whether the client is this machine, which needs no access token
+    pub local: bool,
+    pub user_agent: Option<String>,
+    pub last_path: String,
+    /// Unix milliseconds
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub requests: u64,
+    /// HTTP-FLV and WS-FLV streams currently open
+    pub open_streams: u32,
+}
+
+#[derive(Debug)]
+struct Viewer {
+    info: ViewerInfo,
+    last_request: Instant,
+}
+
+/// Clients of the preview file server, keyed by address.
+#[derive(Debug, Default)]
+pub struct Viewers {
+    clients: Mutex<HashMap<IpAddr, Viewer>>,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Viewers {
+    pub async fn record(&self, ip: IpAddr, path: &str, user_agent: Option<String>) {
+        let now = unix_millis();
+        let mut clients = self.clients.lock().await;
+        let viewer = clients.entry(ip).or_insert_with(|| Viewer {
+            info: ViewerInfo {
+                address: ip.to_string(),
+                local: ip.is_loopback(),
+                user_agent: None,
+                last_path: String::new(),
+                first_seen: now,
+                last_seen: now,
+                requests: 0,
+                open_streams: 0,
+            },
+            last_request: Instant::now(),
+        });
+        viewer.info.last_path = path.to_string();
+        viewer.info.last_seen = now;
+        viewer.info.requests += 1;
+        if user_agent.is_some() {
+            viewer.info.user_agent = user_agent;
+        }
+        viewer.last_request = Instant::now();
+    }
+
+    pub async fn stream_opened(&self, ip: IpAddr) {
+        if let Some(viewer) = self.clients.lock().await.get_mut(&ip) {
+            viewer.info.open_streams += 1;
+        }
+    }
+
+    pub async fn stream_closed(&self, ip: IpAddr) {
+        if let Some(viewer) = self.clients.lock().await.get_mut(&ip) {
+            viewer.info.open_streams = viewer.info.open_streams.saturating_sub(1);
+            viewer.info.last_seen = unix_millis();
+            viewer.last_request = Instant::now();
+        }
+    }
+
+    /// Clients with an open stream or a recent request. Others are forgotten.
+    pub async fn active(&self) -> Vec<ViewerInfo> {
+        let mut clients = self.clients.lock().await;
+        clients.retain(|_, viewer| {
+            viewer.info.open_streams > 0 || viewer.last_request.elapsed() < ACTIVE_WINDOW
+        });
+        let mut viewers: Vec<ViewerInfo> =
+            clients.values().map(|viewer| viewer.info.clone()).collect();
+        viewers.sort_by_key(|viewer| viewer.first_seen);
+        viewers
+    }
+}
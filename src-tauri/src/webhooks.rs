@@ -0,0 +1,334 @@
+use crate::{
+    context::AppContext,
+    db::{self, DeliveryAttempt, Webhook},
+    events::{AppEvents, BusEvent},
+};
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the raw body, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "x-duplicast-signature";
+pub const EVENT_HEADER: &str = "x-duplicast-event";
+const TEST_EVENT: &str = "webhook-test";
+
+const MAX_ATTEMPTS: u32 = 6;
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events a webhook can subscribe to
+pub const WEBHOOK_EVENTS: [AppEvents; 5] = [
+    AppEvents::StreamActive,
+    AppEvents::StreamEnded,
+    AppEvents::RelayFailed,
+    AppEvents::RelayRecovered,
+    AppEvents::EncoderCrashed,
+];
+
+#[derive(Debug, Serialize)]
+struct WebhookBody<'a> {
+    webhook_id: i64,
+    event: &'a str,
+    payload: &'a serde_json::Value,
+    /// Unix milliseconds
+    timestamp: u64,
+}
+
+fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("Failed to build webhook client")
+    })
+}
+
+fn is_webhook_event(event: &str) -> bool {
+    WEBHOOK_EVENTS.iter().any(|e| e.as_str() == event)
+}
+
+/// Checks a webhook's URL and event list, returning the events as stored.
+pub fn validate(url: &str, events: &[String]) -> Result<String, String> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err("Webhook URL must start with http:// or https://".into());
+    }
+    if events.is_empty() {
+        return Err("Pick at least one event".into());
+    }
+    if let Some(unknown) = events.iter().find(|event| !is_webhook_event(event)) {
+        return Err(format!("Unknown webhook event: {}", unknown));
+    }
+    Ok(events.join(","))
+}
+
+pub fn generate_secret() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether a failed attempt is worth repeating. Other client errors mean the
+/// receiver rejected the delivery and won't change its mind.
+fn should_retry(status: Option<StatusCode>) -> bool {
+    match status {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => true,
+    }
+}
+
+async fn post(webhook: &Webhook, event: &str, body: &str, attempt: u32) -> DeliveryAttempt {
+    let result = client()
+        .post(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, event)
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, body.as_bytes()))
+        .body(body.to_string())
+        .send()
+        .await;
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            DeliveryAttempt {
+                attempt,
+                status_code: Some(status.as_u16()),
+                error: (!status.is_success()).then(|| status.to_string()),
+                success: status.is_success(),
+            }
+        }
+        Err(e) => DeliveryAttempt {
+            attempt,
+            status_code: None,
+            error: Some(e.to_string()),
+            success: false,
+        },
+    }
+}
+
+async fn log_attempt(webhook: &Webhook, event: &str, body: &str, attempt: &DeliveryAttempt) {
+    if let Err(e) =
+        db::log_webhook_delivery(webhook.id, event, body, attempt, db::get_db_pool()).await
+    {
+        eprintln!("⚠️ Failed to log webhook delivery: {}", e);
+    }
+}
+
+/// POSTs the event until the receiver accepts it, backing off between attempts.
+async fn deliver(webhook: Webhook, event: &'static str, body: String) {
+    deliver_with_backoff(webhook, event, body, FIRST_RETRY).await
+}
+
+/// `deliver` starting from the given wait, which doubles after each failure.
+async fn deliver_with_backoff(
+    webhook: Webhook,
+    event: &'static str,
+    body: String,
+    first_retry: Duration,
+) {
+    let mut backoff = first_retry;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = post(&webhook, event, &body, attempt).await;
+        log_attempt(&webhook, event, &body, &result).await;
+        if result.success {
+            return;
+        }
+        let retry = should_retry(
+            result
+                .status_code
+                .and_then(|code| StatusCode::from_u16(code).ok()),
+        );
+        if !retry || attempt == MAX_ATTEMPTS {
+            eprintln!(
+                "❌ Webhook {} gave up on {} after {} attempt(s): {}",
+                webhook.id,
+                event,
+                attempt,
+                result.error.unwrap_or_default()
+            );
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RETRY);
+    }
+}
+
+fn render_body(
+    webhook_id: i64,
+    event: &str,
+    payload: &serde_json::Value,
+    timestamp: u64,
+) -> String {
+    serde_json::to_string(&WebhookBody {
+        webhook_id,
+        event,
+        payload,
+        timestamp,
+    })
+    .unwrap_or_default()
+}
+
+/// Starts a delivery to every enabled webhook subscribed to the event. Each
+/// delivery retries on its own, so a slow receiver doesn't hold up the others.
+async fn dispatch(event: BusEvent) {
+    let webhooks = match db::get_webhooks(db::get_db_pool()).await {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            eprintln!("⚠️ Failed to load webhooks: {}", e);
+            return;
+        }
+    };
+    for webhook in webhooks {
+        if !webhook.enabled || !webhook.subscribes_to(event.event) {
+            continue;
+        }
+        let body = render_body(webhook.id, event.event, &event.payload, event.timestamp);
+        tokio::spawn(deliver(webhook, event.event, body));
+    }
+}
+
+/// Follows the event bus and dispatches the events webhooks can subscribe to.
+pub fn start_dispatcher(app: AppContext) {
+    let mut rx = app.state().events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) if is_webhook_event(event.event) => {
+                    tokio::spawn(dispatch(event));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("⚠️ Webhooks missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Sends one unretried test delivery and reports how the receiver answered.
+pub async fn send_test(webhook: &Webhook) -> DeliveryAttempt {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let body = render_body(webhook.id, TEST_EVENT, &serde_json::Value::Null, timestamp);
+    let result = post(webhook, TEST_EVENT, &body, 1).await;
+    log_attempt(webhook, TEST_EVENT, &body, &result).await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+    use warp::{http::HeaderMap, Filter};
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn retries_server_errors_and_rate_limits_only() {
+        assert!(should_retry(None));
+        assert!(should_retry(Some(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(should_retry(Some(StatusCode::BAD_GATEWAY)));
+        assert!(should_retry(Some(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!should_retry(Some(StatusCode::BAD_REQUEST)));
+        assert!(!should_retry(Some(StatusCode::NOT_FOUND)));
+        assert!(!should_retry(Some(StatusCode::GONE)));
+    }
+
+    #[test]
+    fn validates_url_and_events() {
+        let events = vec!["stream-active".to_string(), "relay-failed".to_string()];
+        assert_eq!(
+            validate("https://example.com/hook", &events),
+            Ok("stream-active,relay-failed".into())
+        );
+        assert!(validate("http://localhost:9000", &events).is_ok());
+        assert!(validate("ftp://example.com", &events).is_err());
+        assert!(validate("example.com", &events).is_err());
+        assert!(validate("https://example.com", &[]).is_err());
+        let unknown = vec!["audio-levels".to_string()];
+        assert_eq!(
+            validate("https://example.com", &unknown),
+            Err("Unknown webhook event: audio-levels".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_until_the_receiver_accepts() {
+        let dir = std::env::temp_dir().join(format!("duplicast-webhooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        db::init_db(&dir).await.unwrap();
+        let pool = db::get_db_pool();
+
+        let requests = Arc::new(AtomicU32::new(0));
+        let secret = "test-secret";
+        let receiver = {
+            let requests = requests.clone();
+            warp::post()
+                .and(warp::header::headers_cloned())
+                .and(warp::body::bytes())
+                .map(move |headers: HeaderMap, body: warp::hyper::body::Bytes| {
+                    assert_eq!(headers[SIGNATURE_HEADER], sign(secret, &body).as_str());
+                    assert_eq!(headers[EVENT_HEADER], "stream-ended");
+                    let status = match requests.fetch_add(1, Ordering::SeqCst) {
+                        0 => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        _ => warp::http::StatusCode::OK,
+                    };
+                    warp::reply::with_status("", status)
+                })
+        };
+        let (addr, server) = warp::serve(receiver).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let url = format!("http://{}/hook", addr);
+        let id = db::add_webhook(&url, secret, "stream-ended", pool)
+            .await
+            .unwrap();
+        let webhook = db::get_webhook(id, pool).await.unwrap();
+        let body = render_body(id, "stream-ended", &serde_json::json!("key"), 0);
+        deliver_with_backoff(
+            webhook,
+            "stream-ended",
+            body.clone(),
+            Duration::from_millis(10),
+        )
+        .await;
+
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        let deliveries = db::get_webhook_deliveries(Some(id), 10, pool)
+            .await
+            .unwrap();
+        // newest first
+        let attempts: Vec<_> = deliveries
+            .iter()
+            .map(|d| (d.attempt, d.status_code, d.success))
+            .collect();
+        assert_eq!(attempts, [(2, Some(200), true), (1, Some(500), false)]);
+        assert!(deliveries
+            .iter()
+            .all(|d| d.event == "stream-ended" && d.payload == body));
+    }
+}